@@ -0,0 +1,398 @@
+use crate::Executor;
+use std::{error::Error, fmt::Display};
+
+/// How deep variables whose values are expressions themselves may be followed
+const MAX_DEPTH: usize = 64;
+
+/// Binary operators from the loosest to the tightest binding, after `||` and `&&`
+const LEVELS: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Every operator, longer ones first so they are matched before their prefixes
+const OPERATORS: &[&str] = &[
+    "**=", "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=",
+    "/=", "%=", "&=", "^=", "|=", "++", "--", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|",
+    "!", "~", "?", ":", "=", ",", "(", ")",
+];
+
+const ASSIGNMENTS: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "**=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArithError {
+    /// A token that doesn't belong, or `None` if the expression ended too early
+    Syntax(Option<String>),
+    InvalidNumber(String),
+    DivisionByZero,
+    NegativeExponent,
+    TooDeep,
+}
+
+impl Display for ArithError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithError::Syntax(Some(token)) => write!(f, "syntax error near `{}`", token),
+            ArithError::Syntax(None) => write!(f, "syntax error: operand expected"),
+            ArithError::InvalidNumber(number) => write!(f, "{}: invalid number", number),
+            ArithError::DivisionByZero => write!(f, "division by zero"),
+            ArithError::NegativeExponent => write!(f, "exponent less than 0"),
+            ArithError::TooDeep => write!(f, "expression recursion level exceeded"),
+        }
+    }
+}
+
+impl Error for ArithError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Parse an integer literal, which may be hexadecimal with `0x` or octal with a leading `0`
+fn parse_number(text: &str) -> Result<i64, ArithError> {
+    let invalid = || ArithError::InvalidNumber(text.to_string());
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if text.len() > 1 && text.starts_with('0') {
+            (&text[1..], 8)
+        } else {
+            (text, 10)
+        };
+    u64::from_str_radix(digits, radix)
+        .map(|number| number as i64)
+        .map_err(|_| invalid())
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(chr) = rest.chars().next() {
+        let len = if chr.is_ascii_alphanumeric() || chr == '_' {
+            let len = rest
+                .find(|chr: char| !chr.is_ascii_alphanumeric() && chr != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            tokens.push(if chr.is_ascii_digit() {
+                Token::Number(parse_number(word)?)
+            } else {
+                Token::Name(word.to_string())
+            });
+            len
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    op.len()
+                }
+                None => return Err(ArithError::Syntax(Some(rest.to_string()))),
+            }
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Apply a binary operator
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, ArithError> {
+    Ok(match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err(ArithError::DivisionByZero),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" if rhs < 0 => return Err(ArithError::NegativeExponent),
+        "**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "&" => lhs & rhs,
+        "^" => lhs ^ rhs,
+        "|" => lhs | rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        op => unreachable!("{} is not a binary operator", op),
+    })
+}
+
+/// Evaluates an expression, with every method taking whether it is only being parsed so
+/// branches which are not taken have no side effects
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    shell: &'a mut Executor,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Consume one of the given operators if it comes next
+    fn eat(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &'static str) -> Result<(), ArithError> {
+        match self.eat(&[op]) {
+            Some(_) => Ok(()),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    /// The error for whatever token is at the current position
+    fn unexpected(&self) -> ArithError {
+        ArithError::Syntax(self.peek().map(ToString::to_string))
+    }
+
+    /// The value of a variable, evaluating it if it holds an expression
+    fn var(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = self.shell.get_var(name).unwrap_or_default();
+        if value.trim().is_empty() {
+            return Ok(0);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::TooDeep);
+        }
+        evaluate(self.shell, &value, self.depth + 1)
+    }
+
+    fn set(&mut self, name: &str, value: i64, skip: bool) {
+        if !skip {
+            self.shell.set_var(name, value.to_string());
+        }
+    }
+
+    fn comma(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let mut value = self.assignment(skip)?;
+        while self.eat(&[","]).is_some() {
+            value = self.assignment(skip)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, skip: bool) -> Result<i64, ArithError> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.peek(), self.tokens.get(self.pos + 1))
+        {
+            if ASSIGNMENTS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let mut value = self.assignment(skip)?;
+                if op != "=" && !skip {
+                    let current = self.var(&name)?;
+                    value = apply(&op[..op.len() - 1], current, value)?;
+                }
+                self.set(&name, value, skip);
+                return Ok(value);
+            }
+        }
+        self.ternary(skip)
+    }
+
+    fn ternary(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let condition = self.or(skip)?;
+        if self.eat(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        let then = self.assignment(skip || condition == 0)?;
+        self.expect(":")?;
+        let otherwise = self.ternary(skip || condition != 0)?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn or(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let mut value = self.and(skip)?;
+        while self.eat(&["||"]).is_some() {
+            let rhs = self.and(skip || value != 0)?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let mut value = self.binary(0, skip)?;
+        while self.eat(&["&&"]).is_some() {
+            let rhs = self.binary(0, skip || value == 0)?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn binary(&mut self, level: usize, skip: bool) -> Result<i64, ArithError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.power(skip);
+        };
+        let mut value = self.binary(level + 1, skip)?;
+        while let Some(op) = self.eat(ops) {
+            let rhs = self.binary(level + 1, skip)?;
+            value = if skip { 0 } else { apply(op, value, rhs)? };
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let base = self.unary(skip)?;
+        if self.eat(&["**"]).is_none() {
+            return Ok(base);
+        }
+        let exponent = self.power(skip)?;
+        if skip {
+            Ok(0)
+        } else {
+            apply("**", base, exponent)
+        }
+    }
+
+    fn unary(&mut self, skip: bool) -> Result<i64, ArithError> {
+        match self.eat(&["!", "~", "-", "+", "++", "--"]) {
+            Some("!") => Ok((self.unary(skip)? == 0) as i64),
+            Some("~") => Ok(!self.unary(skip)?),
+            Some("-") => Ok(self.unary(skip)?.wrapping_neg()),
+            Some("+") => self.unary(skip),
+            Some(op) => {
+                let name = match self.peek() {
+                    Some(Token::Name(name)) => name.clone(),
+                    _ => return Err(self.unexpected()),
+                };
+                self.pos += 1;
+                let value = self
+                    .var(&name)?
+                    .wrapping_add(if op == "++" { 1 } else { -1 });
+                self.set(&name, value, skip);
+                Ok(value)
+            }
+            None => self.primary(skip),
+        }
+    }
+
+    fn primary(&mut self, skip: bool) -> Result<i64, ArithError> {
+        let token = self.peek().cloned().ok_or_else(|| self.unexpected())?;
+        self.pos += 1;
+        match token {
+            Token::Number(number) => Ok(number),
+            Token::Op("(") => {
+                let value = self.comma(skip)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Name(name) => {
+                let value = self.var(&name)?;
+                match self.eat(&["++", "--"]) {
+                    Some(op) => {
+                        let new = value.wrapping_add(if op == "++" { 1 } else { -1 });
+                        self.set(&name, new, skip);
+                        Ok(value)
+                    }
+                    None => Ok(value),
+                }
+            }
+            Token::Op(_) => {
+                self.pos -= 1;
+                Err(self.unexpected())
+            }
+        }
+    }
+}
+
+fn evaluate(shell: &mut Executor, expr: &str, depth: usize) -> Result<i64, ArithError> {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        shell,
+        depth,
+    };
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = evaluator.comma(false)?;
+    match evaluator.peek() {
+        Some(_) => Err(evaluator.unexpected()),
+        None => Ok(value),
+    }
+}
+
+impl Executor {
+    /// Evaluate an arithmetic expression whose words have already been expanded
+    pub fn arith(&mut self, expr: &str) -> Result<i64, ArithError> {
+        evaluate(self, expr, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_test;
+
+    #[test]
+    fn evaluates_expressions() {
+        let output = run_test(
+            "echo $((1 + 2 * 3)) $(((1 + 2) * 3)) $((7 / 2)) $((7 % 3)) $((-4)) $((2 ** 10)) $((1 < 2 && 2 > 3))",
+        );
+        assert_eq!(output.stdout, "7 9 3 1 -4 1024 0\n");
+    }
+
+    #[test]
+    fn uses_variables() {
+        let output =
+            run_test("x=5; echo $((x * 2)) $(($x + 1)) $((x > 3 && x < 10)); echo $((y + 1))");
+        assert_eq!(output.stdout, "10 6 1\n1\n");
+    }
+
+    #[test]
+    fn runs_arithmetic_commands() {
+        let output =
+            run_test("(( 2 > 1 )) && echo yes; (( 0 )) || echo no; x=1; (( x += 2 )); echo $x");
+        assert_eq!(output.stdout, "yes\nno\n3\n");
+    }
+
+    #[test]
+    fn expands_only_parameters_and_substitutions() {
+        let output = run_test(
+            "x=2; echo $((1<(2+3))) $(( \"$x\" + 1 )) $(( `echo 2` * $(echo 3) ))
+            (( 1<(2+3) )) && echo yes",
+        );
+        assert_eq!(output.stdout, "1 3 6\nyes\n");
+        assert_eq!(output.stderr, "");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let output = run_test("echo $((1 / 0))");
+        assert_eq!(output.status, 1);
+        assert_eq!(output.stdout, "");
+        assert_eq!(output.stderr, "1 / 0: division by zero\n");
+    }
+}
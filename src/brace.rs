@@ -0,0 +1,166 @@
+use crate::lexer::quoted_end;
+
+/// Expand the braces in a raw word, like `a{b,c}` into `ab` and `ac` or `{1..3}` into `1`, `2`
+/// and `3`
+pub fn expand_braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' | '"' | '`' | '$' => i = quoted_end(&chars, i).unwrap_or(chars.len()),
+            '<' | '>' if chars.get(i + 1) == Some(&'(') => {
+                i = quoted_end(&chars, i).unwrap_or(chars.len())
+            }
+            '{' => {
+                if let Some((end, items)) = brace(&chars, i) {
+                    let prefix: &String = &chars[..i].iter().collect();
+                    let suffixes = expand_braces(&chars[end..].iter().collect::<String>());
+                    return items
+                        .iter()
+                        .flat_map(|item| {
+                            suffixes
+                                .iter()
+                                .map(move |suffix| format!("{}{}{}", prefix, item, suffix))
+                        })
+                        .collect();
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    vec![word.to_string()]
+}
+
+/// Find the items of the brace expression starting at `start`, along with the index just past
+/// it, if it is one
+fn brace(chars: &[char], start: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = start + 1;
+    loop {
+        match *chars.get(i)? {
+            '\\' => {
+                i += 2;
+                continue;
+            }
+            '\'' | '"' | '`' | '$' => {
+                i = quoted_end(chars, i)?;
+                continue;
+            }
+            '<' | '>' if chars.get(i + 1) == Some(&'(') => {
+                i = quoted_end(chars, i)?;
+                continue;
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => break,
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    if commas.is_empty() {
+        let text: String = chars[start + 1..i].iter().collect();
+        return Some((i + 1, range(&text)?));
+    }
+    let mut items = Vec::new();
+    let mut from = start + 1;
+    for comma in commas.into_iter().chain([i]) {
+        items.extend(expand_braces(
+            &chars[from..comma].iter().collect::<String>(),
+        ));
+        from = comma + 1;
+    }
+    Some((i + 1, items))
+}
+
+/// Expand a range like `1..10`, `01..10..2` or `a..z`
+fn range(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let (start, end, step) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?.checked_abs()?.max(1)),
+        _ => return None,
+    };
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let width = if is_padded(start) || is_padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(
+            sequence(first, last, step)
+                .into_iter()
+                .map(|value| format!("{:0width$}", value, width = width))
+                .collect(),
+        );
+    }
+    let (first, last) = (single_char(start)?, single_char(end)?);
+    Some(
+        sequence(first as i64, last as i64, step)
+            .into_iter()
+            .filter_map(|value| char::from_u32(value as u32))
+            .map(|chr| {
+                // the items are still raw words, so keep punctuation from meaning anything
+                if chr.is_alphanumeric() {
+                    chr.to_string()
+                } else {
+                    format!("\\{}", chr)
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Whether a number in a range has leading zeros, which pads every number to the same width
+fn is_padded(number: &str) -> bool {
+    let digits = number.trim_start_matches(['-', '+']);
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    let chr = chars.next()?;
+    chars.next().is_none().then_some(chr)
+}
+
+/// The values from `first` to `last` counting by `step`, in whichever direction that is
+fn sequence(first: i64, last: i64, step: i64) -> Vec<i64> {
+    let mut values = Vec::new();
+    let mut value = first;
+    while (first <= last && value <= last) || (first > last && value >= last) {
+        values.push(value);
+        let next = if first <= last {
+            value.checked_add(step)
+        } else {
+            value.checked_sub(step)
+        };
+        match next {
+            Some(next) => value = next,
+            None => break,
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_test;
+
+    #[test]
+    fn expands_lists() {
+        let output = run_test("echo a{b,c}d {x,y{1,2}} '{p,q}' {single} {,e}f");
+        assert_eq!(output.stdout, "abd acd x y1 y2 {p,q} {single} f ef\n");
+    }
+
+    #[test]
+    fn expands_ranges() {
+        let output = run_test("echo {1..3} {3..1} {a..c} {01..03} {0..10..5} {1..2}{a,b}");
+        assert_eq!(
+            output.stdout,
+            "1 2 3 3 2 1 a b c 01 02 03 0 5 10 1a 1b 2a 2b\n"
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    exec::{Flow, Io},
+    jobs::JobState,
+    vars::{is_name, split_assignment, Variable},
+    Executor,
+};
+use std::{
+    env,
+    error::Error,
+    ffi::CString,
+    fs,
+    io::{self, Write},
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Commands which are handled by the shell itself
+pub const BUILTINS: &[&str] = &[
+    "cd", "exit", "jobs", "fg", "bg", "disown", "export", "unset", "env", "break", "continue",
+    "local", "return", "source", ".", "alias", "unalias", "abbr",
+];
+
+/// Whether a command is handled by the shell itself
+pub fn is_builtin(args: &[String]) -> bool {
+    // `env` with arguments runs a command, which is left to the real `env`
+    BUILTINS.contains(&args[0].as_str()) && !(args[0] == "env" && args.len() > 1)
+}
+
+/// Resolve a directory to change into, failing like `chdir` would if it can't be
+fn enterable_dir(path: &Path) -> io::Result<PathBuf> {
+    let dir = fs::canonicalize(path)?;
+    if !dir.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    let name = CString::new(dir.as_os_str().as_bytes())?;
+    if unsafe { libc::access(name.as_ptr(), libc::X_OK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(dir)
+}
+
+/// Whether a word can be the name of an alias, which has to be typed as it is
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|chr: char| chr.is_whitespace() || "|&;<>()$`\\\"'=/".contains(chr))
+}
+
+/// Quote a value so it reads back as the same word
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for chr in value.chars() {
+        if matches!(chr, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(chr);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Executor {
+    /// Run a builtin command and return its status
+    pub fn run_builtin(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let result = match args[0].as_str() {
+            "cd" => self.cd(args, io),
+            "exit" => self.exit(args, io),
+            "jobs" => self.jobs(io),
+            "fg" => self.fg(args, io),
+            "bg" => self.bg(args, io),
+            "disown" => self.disown(args, io),
+            "export" => self.export(args, io),
+            "unset" => self.unset(args, io),
+            "env" => self.env(io),
+            "break" | "continue" => self.break_loop(args, io),
+            "local" => self.local(args, io),
+            "return" => self.return_from(args, io),
+            "source" | "." => self.source(args, io),
+            "alias" => self.alias(args, io),
+            "unalias" => self.unalias(args, io),
+            "abbr" => self.abbr(args, io),
+            name => unreachable!("{} is not a builtin", name),
+        };
+        match result {
+            // failing to write output, like to a full disk, fails the command but not the shell
+            Err(err) if err.is::<io::Error>() => {
+                io.error(format!("{}: {}", args[0], err));
+                Ok(1)
+            }
+            result => result,
+        }
+    }
+
+    fn cd(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if args.len() == 1 {
+            writeln!(io.stdout, "{}", self.path.display())?;
+            return Ok(0);
+        }
+        let path = PathBuf::from(&args[1]);
+        // the shell keeps its own directory rather than changing the process', so embedded
+        // shells don't move each other around
+        match enterable_dir(&self.path.join(&path)) {
+            Ok(dir) => {
+                let old = mem::replace(&mut self.path, dir);
+                self.set_var("OLDPWD", old.display().to_string());
+                self.set_var("PWD", self.path.display().to_string());
+                Ok(0)
+            }
+            Err(err) => {
+                io.error(format!("cd: {}: {}", path.display(), err));
+                Ok(1)
+            }
+        }
+    }
+
+    fn exit(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let status = match args.get(1).map(|arg| arg.parse()) {
+            Some(Ok(status)) => status,
+            Some(Err(_)) => {
+                io.error(format!("exit: {}: numeric argument required", args[1]));
+                2
+            }
+            None => self.status,
+        };
+        if self.job_control.is_some() {
+            writeln!(io.stdout, "See you later, Bye!")?;
+        }
+        self.exit = true;
+        Ok(status)
+    }
+
+    fn jobs(&mut self, io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        self.poll_jobs();
+        let (current, _) = self.current_jobs();
+        for job in &self.jobs {
+            writeln!(io.stdout, "{}", job.describe(current == Some(job.id)))?;
+        }
+        self.jobs
+            .retain(|job| !matches!(job.state(), JobState::Done(_)));
+        Ok(0)
+    }
+
+    fn fg(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if self.job_control.is_none() {
+            io.error("fg: no job control");
+            return Ok(1);
+        }
+        let idx = match self.find_job(args.get(1).map(String::as_str)) {
+            Ok(idx) => idx,
+            Err(err) => {
+                io.error(format!("fg: {}", err));
+                return Ok(1);
+            }
+        };
+        let mut job = self.jobs.remove(idx);
+        writeln!(io.stdout, "{}", job.command)?;
+        job.resume();
+        Ok(self.wait_for_job(job)?)
+    }
+
+    fn bg(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if self.job_control.is_none() {
+            io.error("bg: no job control");
+            return Ok(1);
+        }
+        let idx = match self.find_job(args.get(1).map(String::as_str)) {
+            Ok(idx) => idx,
+            Err(err) => {
+                io.error(format!("bg: {}", err));
+                return Ok(1);
+            }
+        };
+        let job = &mut self.jobs[idx];
+        job.resume();
+        writeln!(io.stdout, "[{}] {} &", job.id, job.command)?;
+        Ok(0)
+    }
+
+    fn disown(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        match self.find_job(args.get(1).map(String::as_str)) {
+            Ok(idx) => {
+                self.jobs.remove(idx);
+                Ok(0)
+            }
+            Err(err) => {
+                io.error(format!("disown: {}", err));
+                Ok(1)
+            }
+        }
+    }
+
+    fn export(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if args.len() == 1 {
+            let mut exported: Vec<(&String, &String)> = self.exported_vars().collect();
+            exported.sort();
+            for (name, value) in exported {
+                writeln!(io.stdout, "export {}={}", name, quote(value))?;
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in &args[1..] {
+            let (name, value) = match split_assignment(arg) {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None if is_name(arg) => (arg.as_str(), None),
+                None => {
+                    io.error(format!("export: {}: not a valid name", arg));
+                    status = 1;
+                    continue;
+                }
+            };
+            let var = self.vars.entry(name.to_string()).or_insert(Variable {
+                value: String::new(),
+                exported: true,
+            });
+            var.exported = true;
+            if let Some(value) = value {
+                var.value = value;
+            }
+        }
+        Ok(status)
+    }
+
+    fn unset(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let mut status = 0;
+        let functions = args.get(1).is_some_and(|arg| arg == "-f");
+        let start = if functions || args.get(1).is_some_and(|arg| arg == "-v") {
+            2
+        } else {
+            1
+        };
+        for name in &args[start..] {
+            if functions {
+                self.functions.remove(name);
+            } else if is_name(name) {
+                self.vars.remove(name);
+            } else {
+                io.error(format!("unset: {}: not a valid name", name));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+
+    fn break_loop(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let count = match args.get(1).map(|arg| arg.parse::<usize>()) {
+            None => 1,
+            Some(Ok(count)) if count > 0 => count,
+            Some(_) => {
+                io.error(format!("{}: {}: loop count out of range", args[0], args[1]));
+                return Ok(1);
+            }
+        };
+        if self.loops == 0 {
+            io.error(format!("{}: only meaningful in a loop", args[0]));
+            return Ok(0);
+        }
+        let count = count.min(self.loops);
+        self.flow = if args[0] == "break" {
+            Flow::Break(count)
+        } else {
+            Flow::Continue(count)
+        };
+        Ok(0)
+    }
+
+    fn local(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let mut status = 0;
+        for arg in &args[1..] {
+            let (name, value) = match split_assignment(arg) {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None if is_name(arg) => (arg.as_str(), None),
+                None => {
+                    io.error(format!("local: {}: not a valid name", arg));
+                    status = 1;
+                    continue;
+                }
+            };
+            if !self.make_local(name) {
+                io.error("local: can only be used in a function");
+                return Ok(1);
+            }
+            if let Some(value) = value {
+                self.set_var(name, value);
+            }
+        }
+        Ok(status)
+    }
+
+    fn return_from(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if self.locals.is_empty() {
+            io.error("return: can only return from a function");
+            return Ok(1);
+        }
+        let status = match args.get(1).map(|arg| arg.parse::<i32>()) {
+            None => self.status,
+            Some(Ok(status)) => status,
+            Some(Err(_)) => {
+                io.error(format!("return: {}: numeric argument required", args[1]));
+                2
+            }
+        };
+        self.flow = Flow::Return;
+        Ok(status)
+    }
+
+    fn source(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let Some(file) = args.get(1) else {
+            io.error(format!("{}: filename argument required", args[0]));
+            return Ok(2);
+        };
+        // like other shells, look through PATH for names without a slash before trying the
+        // current directory
+        let path = (!file.contains('/'))
+            .then(|| self.get_var("PATH"))
+            .flatten()
+            .and_then(|paths| {
+                env::split_paths(&paths)
+                    .map(|dir| dir.join(file))
+                    .find(|path| path.is_file())
+            })
+            .unwrap_or_else(|| self.path.join(file));
+        match self.source_file(&path, &args[2..], io) {
+            Ok(status) => Ok(status),
+            Err(err) => {
+                io.error(format!("{}: {}: {}", args[0], file, err));
+                Ok(1)
+            }
+        }
+    }
+
+    fn alias(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if args.len() == 1 {
+            let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+            aliases.sort();
+            for (name, value) in aliases {
+                writeln!(io.stdout, "alias {}={}", name, quote(value))?;
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in &args[1..] {
+            match arg.split_once('=') {
+                Some((name, _)) if !is_alias_name(name) => {
+                    io.error(format!("alias: {}: not a valid name", name));
+                    status = 1;
+                }
+                Some((name, value)) => {
+                    self.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match self.aliases.get(arg) {
+                    Some(value) => writeln!(io.stdout, "alias {}={}", arg, quote(value))?,
+                    None => {
+                        io.error(format!("alias: {}: not found", arg));
+                        status = 1;
+                    }
+                },
+            }
+        }
+        Ok(status)
+    }
+
+    fn unalias(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        if args.get(1).is_some_and(|arg| arg == "-a") {
+            self.aliases.clear();
+            return Ok(0);
+        }
+        let mut status = 0;
+        for name in &args[1..] {
+            if self.aliases.remove(name).is_none() {
+                io.error(format!("unalias: {}: not found", name));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+
+    /// `abbr [-a] name expansion...`, `abbr -e name...`, `abbr -l` or `abbr` to show them all
+    fn abbr(&mut self, args: &[String], io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        match args.get(1).map(String::as_str) {
+            None | Some("-s" | "--show") => {
+                let mut abbrs: Vec<(&String, &String)> = self.abbrs.iter().collect();
+                abbrs.sort();
+                for (name, expansion) in abbrs {
+                    writeln!(io.stdout, "abbr -a {} {}", name, quote(expansion))?;
+                }
+                Ok(0)
+            }
+            Some("-l" | "--list") => {
+                let mut names: Vec<&String> = self.abbrs.keys().collect();
+                names.sort();
+                for name in names {
+                    writeln!(io.stdout, "{}", name)?;
+                }
+                Ok(0)
+            }
+            Some("-e" | "--erase") => {
+                let mut status = 0;
+                for name in &args[2..] {
+                    if self.abbrs.remove(name).is_none() {
+                        io.error(format!("abbr: {}: not found", name));
+                        status = 1;
+                    }
+                }
+                Ok(status)
+            }
+            Some(option) => {
+                let start = if matches!(option, "-a" | "--add") {
+                    2
+                } else {
+                    1
+                };
+                match &args[start..] {
+                    [name, expansion @ ..]
+                        if !expansion.is_empty() && !name.contains(char::is_whitespace) =>
+                    {
+                        self.abbrs.insert(name.clone(), expansion.join(" "));
+                        Ok(0)
+                    }
+                    _ => {
+                        io.error("abbr: usage: abbr [-a] name expansion...");
+                        Ok(2)
+                    }
+                }
+            }
+        }
+    }
+
+    fn env(&mut self, io: &mut Io) -> Result<i32, Box<dyn Error>> {
+        let mut exported: Vec<(&String, &String)> = self.exported_vars().collect();
+        exported.sort();
+        for (name, value) in exported {
+            writeln!(io.stdout, "{}={}", name, value)?;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_test;
+    use std::{env, fs, process};
+
+    #[test]
+    fn exports_variables() {
+        let output = run_test(
+            "x=1; sh -c 'echo ${x}.'; export x; sh -c 'echo $x'; export y=2; sh -c 'echo $y'",
+        );
+        assert_eq!(output.stdout, ".\n1\n2\n");
+    }
+
+    #[test]
+    fn unsets_variables() {
+        let output = run_test("export x=1; unset x; echo ${x}.; sh -c 'echo ${x}.'");
+        assert_eq!(output.stdout, ".\n.\n");
+    }
+
+    #[test]
+    fn lists_the_environment() {
+        let output = run_test("export x='a b'; y=1; env");
+        assert!(output.stdout.lines().any(|line| line == "x=a b"));
+        assert!(!output.stdout.lines().any(|line| line.starts_with("y=")));
+    }
+
+    #[test]
+    fn sets_the_environment_of_one_command() {
+        let output =
+            run_test("x=1 sh -c 'echo $x'; echo ${x}.; export y=2; y=3 sh -c 'echo $y'; echo $y");
+        assert_eq!(output.stdout, "1\n.\n3\n2\n");
+    }
+
+    #[test]
+    fn sources_files() {
+        let file = env::temp_dir().join(format!("eish-source-{}", process::id()));
+        fs::write(&file, "sourced=$1-$#\nf() { echo in f; }\n").unwrap();
+        let output = run_test(&format!(
+            "source {} a b; echo $sourced $#; f",
+            file.display()
+        ));
+        fs::remove_file(file).unwrap();
+        assert_eq!(output.stdout, "a-2 0\nin f\n");
+    }
+
+    #[test]
+    fn sources_files_with_the_builtins_streams() {
+        let file = env::temp_dir().join(format!("eish-source-streams-{}", process::id()));
+        fs::write(&file, "echo out; echo err >&2\n").unwrap();
+        let output = run_test(&format!(
+            "source {0} | tr a-z A-Z; source {0} 2>&1",
+            file.display()
+        ));
+        fs::remove_file(file).unwrap();
+        assert_eq!(output.stdout, "OUT\nout\nerr\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    #[test]
+    fn fails_on_write_errors() {
+        let output = run_test("env > /dev/full; echo $?");
+        assert_eq!(output.stdout, "1\n");
+        assert_eq!(
+            output.stderr,
+            "env: No space left on device (os error 28)\n"
+        );
+    }
+
+    #[test]
+    fn changes_directory() {
+        let cwd = env::current_dir().unwrap();
+        let output = run_test("cd /usr; pwd; cd ..; pwd; echo $PWD $OLDPWD; cd /nonexistent");
+        assert_eq!(output.stdout, "/usr\n/\n/ /usr\n");
+        assert_eq!(output.status, 1);
+        assert_eq!(env::current_dir().unwrap(), cwd);
+    }
+}
@@ -0,0 +1,1104 @@
+use crate::{
+    builtins::is_builtin,
+    expand::ExpandError,
+    glob::matches,
+    jobs::{enter_job, reset_job_signals, wait_pid, Job},
+    lexer::RedirectOp,
+    parser::{
+        parse, AndOr, Ast, Case, Command, Compound, Connector, For, If, List, Pipeline, Redirect,
+        SimpleCommand, While,
+    },
+    vars::Variable,
+    Executor,
+};
+use crossterm::style::Stylize;
+use libc::pid_t;
+use std::{
+    env,
+    error::Error,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, IsTerminal, Read, Seek, Write},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    path::Path,
+    process::{self, ExitStatus},
+};
+
+/// The standard streams a command is run with
+#[derive(Debug)]
+pub struct Io {
+    pub stdin: File,
+    pub stdout: File,
+    pub stderr: File,
+}
+
+impl Io {
+    /// The shell's own standard streams
+    pub fn inherit() -> io::Result<Self> {
+        Ok(Self {
+            stdin: File::from(io::stdin().as_fd().try_clone_to_owned()?),
+            stdout: File::from(io::stdout().as_fd().try_clone_to_owned()?),
+            stderr: File::from(io::stderr().as_fd().try_clone_to_owned()?),
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        })
+    }
+
+    /// The stream behind one of the standard file descriptors
+    fn stream(&mut self, fd: i32) -> io::Result<&mut File> {
+        match fd {
+            0 => Ok(&mut self.stdin),
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(io::Error::other("bad file descriptor")),
+        }
+    }
+
+    /// Apply a redirection to a target which has already been expanded, which is the text to
+    /// read for here-documents and here-strings. Relative file names are looked up in `dir`.
+    pub fn redirect(&mut self, redirect: &Redirect, target: &str, dir: &Path) -> io::Result<()> {
+        let path = dir.join(target);
+        let file = match redirect.op {
+            RedirectOp::Read => File::open(&path)?,
+            RedirectOp::Write => File::create(&path)?,
+            RedirectOp::Append => OpenOptions::new().append(true).create(true).open(&path)?,
+            RedirectOp::DupRead | RedirectOp::DupWrite => match target.parse() {
+                Ok(fd) => self.stream(fd)?.try_clone()?,
+                // `>& file` is the same as `&> file`
+                Err(_) if redirect.op == RedirectOp::DupWrite => {
+                    self.stdout = File::create(&path)?;
+                    self.stderr = self.stdout.try_clone()?;
+                    return Ok(());
+                }
+                Err(_) => return Err(io::Error::other("bad file descriptor")),
+            },
+            RedirectOp::HereDoc | RedirectOp::HereDocStrip | RedirectOp::HereString => {
+                temp_file(target)?
+            }
+            RedirectOp::WriteAll | RedirectOp::AppendAll => {
+                self.stdout = if redirect.op == RedirectOp::WriteAll {
+                    File::create(&path)?
+                } else {
+                    OpenOptions::new().append(true).create(true).open(&path)?
+                };
+                self.stderr = self.stdout.try_clone()?;
+                return Ok(());
+            }
+        };
+        *self.stream(redirect.fd)? = file;
+        Ok(())
+    }
+
+    /// Report an error on stderr, in red if it is a terminal. There's nowhere left to report it
+    /// if that fails, like when stderr is a full disk, so the command just fails as it would have.
+    pub fn error(&self, msg: impl Display) {
+        let mut stderr = &self.stderr;
+        let _ = if stderr.is_terminal() {
+            writeln!(stderr, "{}", msg.to_string().red())
+        } else {
+            writeln!(stderr, "{}", msg)
+        };
+    }
+}
+
+/// Write text to a temporary file which is gone once it is closed, returning it ready to be
+/// read from
+fn temp_file(text: &str) -> io::Result<File> {
+    let dir = env::temp_dir();
+    let mut n = 0;
+    let (mut file, path) = loop {
+        let path = dir.join(format!("eish-{}-{}", process::id(), n));
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => break (file, path),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(err) => return Err(err),
+        }
+    };
+    // nothing else needs the file, so it goes away as soon as it is closed
+    fs::remove_file(path)?;
+    file.write_all(text.as_bytes())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+/// What an [`Executor::output`] run wrote, along with the status it ended with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Create a pipe, returning its read and write ends
+pub fn pipe() -> io::Result<(File, File)> {
+    let (reader, writer) = io::pipe()?;
+    Ok((
+        File::from(OwnedFd::from(reader)),
+        File::from(OwnedFd::from(writer)),
+    ))
+}
+
+/// Convert a child's exit status to a shell status code
+pub fn status_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
+}
+
+/// The name of a signal which may have killed a child
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        _ => return None,
+    })
+}
+
+/// Where a `break` or `continue` is taking the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Normal,
+    /// Leave this many enclosing loops
+    Break(usize),
+    /// Leave this many enclosing loops minus one, then start the next iteration of the last one
+    Continue(usize),
+    /// Leave the function being run
+    Return,
+}
+
+/// How deeply functions may call each other before the shell gives up
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// A pipeline stage which was either handled by the shell or started as a process
+enum Stage {
+    Done(i32),
+    Running(pid_t),
+}
+
+/// How the processes of a job are being started
+struct Launch {
+    /// Whether commands handled by the shell itself need a process of their own
+    fork: bool,
+    /// Whether the job gets the terminal
+    foreground: bool,
+    /// The job's process group, once its first process has started
+    pgid: Option<pid_t>,
+}
+
+impl Executor {
+    /// Run every command in a list and return the status of the last one
+    pub fn run_list(&mut self, list: &List, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let outer = self.streams.replace(io.try_clone()?);
+        let status = self.run_items(list, io);
+        self.streams = outer;
+        status
+    }
+
+    fn run_items(&mut self, list: &List, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let mut status = 0;
+        for and_or in &list.items {
+            status = if and_or.background {
+                self.run_background(and_or, io)?
+            } else {
+                self.run_and_or(and_or, io)?
+            };
+            if self.interrupted() {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let mut status = self.run_pipeline(&and_or.first, io, true)?;
+        self.status = status;
+        for (connector, pipeline) in &and_or.rest {
+            let run = match connector {
+                Connector::And => status == 0,
+                Connector::Or => status != 0,
+            };
+            if self.interrupted() {
+                break;
+            }
+            if run {
+                status = self.run_pipeline(pipeline, io, true)?;
+                self.status = status;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Run an AST with the shell's own standard streams, returning the status it ends with
+    pub fn run(&mut self, ast: &Ast) -> Result<i32, Box<dyn Error>> {
+        self.run_list(ast, &Io::inherit()?)
+    }
+
+    /// Run an AST, collecting what it writes to stdout and stderr instead of showing it
+    pub fn output(&mut self, ast: &Ast) -> Result<Output, Box<dyn Error>> {
+        let (mut stdout, mut stderr) = (temp_file("")?, temp_file("")?);
+        let io = Io {
+            stdout: stdout.try_clone()?,
+            stderr: stderr.try_clone()?,
+            ..Io::inherit()?
+        };
+        let status = self.run_list(ast, &io)?;
+        let read = |file: &mut File| -> io::Result<String> {
+            let mut text = Vec::new();
+            file.rewind()?;
+            file.read_to_end(&mut text)?;
+            Ok(String::from_utf8_lossy(&text).into_owned())
+        };
+        Ok(Output {
+            status,
+            stdout: read(&mut stdout)?,
+            stderr: read(&mut stderr)?,
+        })
+    }
+
+    /// Run a whole script in the shell with its own standard streams, returning the status it
+    /// ends with
+    pub fn run_script(&mut self, name: &str, source: &str) -> Result<i32, Box<dyn Error>> {
+        self.run_source(name, source, &Io::inherit()?)
+    }
+
+    /// Run a whole script with the given streams, returning the status it ends with
+    fn run_source(&mut self, name: &str, source: &str, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let list = match parse(source) {
+            Ok(list) => list,
+            Err(err) => {
+                io.error(format!("{}: {}", name, err));
+                return Ok(2);
+            }
+        };
+        self.run_list(&list, io)?;
+        Ok(self.status)
+    }
+
+    /// Run a file in the shell, with its own positional parameters if any are given
+    pub fn source_file(
+        &mut self,
+        path: &Path,
+        args: &[String],
+        io: &Io,
+    ) -> Result<i32, Box<dyn Error>> {
+        let source = fs::read_to_string(path)?;
+        if args.is_empty() {
+            return self.run_source(&path.display().to_string(), &source, io);
+        }
+        let mut script_args = vec![self.args[0].clone()];
+        script_args.extend_from_slice(args);
+        let saved_args = mem::replace(&mut self.args, script_args);
+        let status = self.run_source(&path.display().to_string(), &source, io);
+        self.args = saved_args;
+        status
+    }
+
+    /// Whether the commands being run should stop because of `exit`, `break` or `continue`
+    fn interrupted(&self) -> bool {
+        self.exit || self.flow != Flow::Normal
+    }
+
+    /// Start an and-or list as a background job
+    fn run_background(&mut self, and_or: &AndOr, io: &Io) -> Result<i32, Box<dyn Error>> {
+        if and_or.rest.is_empty() {
+            return self.run_pipeline(&and_or.first, io, false);
+        }
+        let mut launch = Launch {
+            fork: true,
+            foreground: false,
+            pgid: None,
+        };
+        let pid = self.fork(Some(&mut launch), |sh| sh.run_and_or(and_or, io))?;
+        self.start_job(Job::new(and_or.to_string(), pid, vec![pid]))?;
+        Ok(0)
+    }
+
+    /// Run a pipeline and return the status of its last command
+    fn run_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        io: &Io,
+        foreground: bool,
+    ) -> Result<i32, Box<dyn Error>> {
+        let mut launch = Launch {
+            fork: !foreground || pipeline.commands.len() > 1,
+            foreground,
+            pgid: None,
+        };
+        let mut pids = Vec::new();
+        // the status of the last command, if it did not need a process
+        let mut status = None;
+        let mut stdin = Some(io.stdin.try_clone()?);
+        let last = pipeline.commands.len() - 1;
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let (next_stdin, stdout) = if i == last {
+                (None, io.stdout.try_clone()?)
+            } else {
+                let (reader, writer) = pipe()?;
+                (Some(reader), writer)
+            };
+            let stage_io = Io {
+                // there is always a stdin for the current stage
+                stdin: stdin.take().unwrap(),
+                stdout,
+                stderr: io.stderr.try_clone()?,
+            };
+            match self.run_command(command, stage_io, &mut launch)? {
+                Stage::Done(code) => status = Some(code),
+                Stage::Running(pid) => {
+                    pids.push(pid);
+                    status = None;
+                }
+            }
+            stdin = next_stdin;
+        }
+        let Some(pgid) = launch.pgid else {
+            return Ok(status.unwrap_or_default());
+        };
+        let job = Job::new(pipeline.to_string(), pgid, pids);
+        if !foreground {
+            self.start_job(job)?;
+            return Ok(0);
+        }
+        let job_status = self.wait_for_job(job)?;
+        Ok(status.unwrap_or(job_status))
+    }
+
+    /// Add a job that was started in the background to the job table and announce it
+    fn start_job(&mut self, job: Job) -> io::Result<()> {
+        // jobs are never created without processes
+        let pid = job.processes.last().unwrap().pid;
+        self.background_pid = Some(pid);
+        let id = self.add_job(job);
+        if self.job_control.is_some() {
+            writeln!(io::stderr(), "[{}] {}", id, pid)?;
+        }
+        Ok(())
+    }
+
+    /// Start a copy of the shell running `run` and return its pid.
+    ///
+    /// The copy becomes part of a job if it is being launched as one, otherwise it stays in the
+    /// shell's own process group.
+    fn fork(
+        &mut self,
+        launch: Option<&mut Launch>,
+        run: impl FnOnce(&mut Executor) -> Result<i32, Box<dyn Error>>,
+    ) -> io::Result<pid_t> {
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                if let Some(control) = self.job_control.take() {
+                    match launch {
+                        Some(launch) => enter_job(
+                            control.terminal,
+                            launch.pgid.unwrap_or(0),
+                            launch.foreground,
+                        ),
+                        // not a job of its own, but it still shouldn't ignore what the shell does
+                        None => reset_job_signals(),
+                    }
+                }
+                unsafe {
+                    libc::signal(libc::SIGINT, libc::SIG_DFL);
+                }
+                self.jobs.clear();
+                let status = run(self).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    1
+                });
+                unsafe { libc::_exit(status) }
+            }
+            pid => {
+                if let Some(launch) = launch {
+                    let pgid = *launch.pgid.get_or_insert(pid);
+                    if self.job_control.is_some() {
+                        unsafe {
+                            libc::setpgid(pid, pgid);
+                        }
+                    }
+                }
+                Ok(pid)
+            }
+        }
+    }
+
+    /// Run a command in a subshell and return what it wrote to stdout, minus trailing newlines
+    pub fn capture(&mut self, input: &str) -> Result<String, ExpandError> {
+        let list = parse(input)?;
+        let (mut reader, writer) = pipe()?;
+        let io = Io {
+            stdout: writer,
+            ..self.inherited_streams()?
+        };
+        let pid = self.fork(None, |sh| sh.run_list(&list, &io))?;
+        drop(io);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        self.status = wait_pid(pid);
+        self.substitution_status = Some(self.status);
+        Ok(String::from_utf8_lossy(&output)
+            .trim_end_matches('\n')
+            .to_string())
+    }
+
+    /// Start the command of a `<(...)` or `>(...)` process substitution, returning the
+    /// `/dev/fd/N` path of a pipe to read its output from or write its input to
+    pub fn substitute_process(&mut self, input: &str, read: bool) -> Result<String, ExpandError> {
+        self.reap_substitutions();
+        let list = parse(input)?;
+        let (reader, writer) = pipe()?;
+        let mut streams = self.inherited_streams()?;
+        let ours = if read {
+            streams.stdout = writer;
+            reader
+        } else {
+            streams.stdin = reader;
+            writer
+        };
+        let fd = ours.as_raw_fd();
+        let pid = self.fork(None, |sh| {
+            unsafe {
+                libc::close(fd);
+            }
+            sh.run_list(&list, &streams)
+        })?;
+        drop(streams);
+        // the command being expanded has to inherit the pipe to be able to open the path
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        self.substitutions.push(ours);
+        self.substitution_pids.push(pid);
+        Ok(format!("/dev/fd/{}", fd))
+    }
+
+    /// The streams substitutions run with, which are those of the list being run if any
+    fn inherited_streams(&self) -> io::Result<Io> {
+        match &self.streams {
+            Some(io) => io.try_clone(),
+            None => Io::inherit(),
+        }
+    }
+
+    /// Clean up after process substitutions which finished, since nothing waits for them
+    pub fn reap_substitutions(&mut self) {
+        self.substitution_pids.retain(|pid| {
+            let mut status = 0;
+            unsafe { libc::waitpid(*pid, &mut status, libc::WNOHANG) == 0 }
+        });
+    }
+
+    /// Expand the values of `name=value` assignments
+    fn expand_assignments(
+        &mut self,
+        assignments: &[(String, String)],
+    ) -> Result<Vec<(String, String)>, ExpandError> {
+        assignments
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), self.expand_word(value)?)))
+            .collect()
+    }
+
+    /// Run something handled by the shell itself, in a process of its own if the job needs one
+    fn run_internal(
+        &mut self,
+        launch: &mut Launch,
+        run: impl FnOnce(&mut Executor) -> Result<i32, Box<dyn Error>>,
+    ) -> Result<Stage, Box<dyn Error>> {
+        Ok(if launch.fork {
+            Stage::Running(self.fork(Some(launch), run)?)
+        } else {
+            Stage::Done(run(self)?)
+        })
+    }
+
+    fn run_command(
+        &mut self,
+        command: &Command,
+        io: Io,
+        launch: &mut Launch,
+    ) -> Result<Stage, Box<dyn Error>> {
+        // process substitutions stay open until the command they were expanded for is done
+        let substitutions = self.substitutions.len();
+        let stage = self.start_command(command, io, launch);
+        self.substitutions.truncate(substitutions);
+        stage
+    }
+
+    fn start_command(
+        &mut self,
+        command: &Command,
+        mut io: Io,
+        launch: &mut Launch,
+    ) -> Result<Stage, Box<dyn Error>> {
+        match command {
+            Command::Simple(command) => self.run_simple(command, io, launch),
+            Command::Compound(compound, redirects) => {
+                if !self.redirect(&mut io, redirects)? {
+                    return Ok(Stage::Done(1));
+                }
+                if let Compound::Subshell(list) = compound {
+                    let pid = self.fork(Some(launch), |sh| sh.run_list(list, &io))?;
+                    return Ok(Stage::Running(pid));
+                }
+                self.run_internal(launch, |sh| sh.run_compound(compound, &io))
+            }
+            Command::Function(function) => self.run_internal(launch, |sh| {
+                sh.functions
+                    .insert(function.name.clone(), function.body.clone());
+                Ok(0)
+            }),
+        }
+    }
+
+    /// Run a command whose first word is an alias, by replacing it with the alias' value
+    fn run_alias(
+        &mut self,
+        name: &str,
+        command: &SimpleCommand,
+        mut io: Io,
+        launch: &mut Launch,
+    ) -> Result<Stage, Box<dyn Error>> {
+        let mut text: Vec<String> = command
+            .assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        text.push(self.aliases[name].clone());
+        text.extend_from_slice(&command.words[1..]);
+        let list = match parse(&text.join(" ")) {
+            Ok(list) => list,
+            Err(err) => {
+                io.error(format!("{}: {}", name, err));
+                return Ok(Stage::Done(2));
+            }
+        };
+        if !self.redirect(&mut io, &command.redirects)? {
+            return Ok(Stage::Done(1));
+        }
+        self.run_internal(launch, |sh| {
+            sh.expanding.push(name.to_string());
+            let status = sh.run_list(&list, &io);
+            sh.expanding.pop();
+            status
+        })
+    }
+
+    /// Call a function with the given arguments as its positional parameters
+    fn call(&mut self, body: &List, args: &[String], io: &Io) -> Result<i32, Box<dyn Error>> {
+        if self.locals.len() >= MAX_CALL_DEPTH {
+            io.error(format!(
+                "{}: maximum function nesting level exceeded",
+                args[0]
+            ));
+            return Ok(1);
+        }
+        let mut call_args = vec![self.args[0].clone()];
+        call_args.extend_from_slice(&args[1..]);
+        let saved_args = mem::replace(&mut self.args, call_args);
+        let saved_loops = mem::take(&mut self.loops);
+        self.locals.push(Vec::new());
+        let status = self.run_list(body, io);
+        // put back the variables `local` hid, most recent first
+        for (name, var) in self.locals.pop().unwrap_or_default().into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+        self.loops = saved_loops;
+        self.args = saved_args;
+        if self.flow == Flow::Return {
+            self.flow = Flow::Normal;
+            return Ok(self.status);
+        }
+        status
+    }
+
+    /// Make a variable local to the function being run, returning false outside of functions
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let Some(frame) = self.locals.last_mut() else {
+            return false;
+        };
+        if !frame.iter().any(|(local, _)| local == name) {
+            frame.push((name.to_string(), self.vars.get(name).cloned()));
+        }
+        self.vars.insert(
+            name.to_string(),
+            Variable {
+                value: String::new(),
+                exported: false,
+            },
+        );
+        true
+    }
+
+    /// Apply redirections to the streams of a command, returning whether they all succeeded
+    fn redirect(&mut self, io: &mut Io, redirects: &[Redirect]) -> io::Result<bool> {
+        for redirect in redirects {
+            let target = match (&redirect.here_doc, redirect.op) {
+                (Some(doc), _) if doc.expand => self.expand_here_doc(&doc.body),
+                (Some(doc), _) => Ok(doc.body.clone()),
+                (None, RedirectOp::HereString) => {
+                    self.expand_word(&redirect.target).map(|text| text + "\n")
+                }
+                (None, _) => self.expand_word(&redirect.target),
+            };
+            let target = match target {
+                Ok(target) => target,
+                Err(err) => {
+                    io.error(err);
+                    return Ok(false);
+                }
+            };
+            if let Err(err) = io.redirect(redirect, &target, &self.path) {
+                io.error(format!("{}: {}", target, err));
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn run_compound(&mut self, compound: &Compound, io: &Io) -> Result<i32, Box<dyn Error>> {
+        match compound {
+            Compound::Arith(expr) => {
+                let value = self
+                    .expand_arith(expr)
+                    .map_err(|err| err.to_string())
+                    .and_then(|expr| {
+                        self.arith(&expr)
+                            .map_err(|err| format!("{}: {}", expr, err))
+                    });
+                match value {
+                    Ok(value) => Ok((value == 0) as i32),
+                    Err(err) => {
+                        io.error(err);
+                        Ok(1)
+                    }
+                }
+            }
+            Compound::If(clause) => self.run_if(clause, io),
+            Compound::While(clause) => self.run_loop(|sh| sh.run_while(clause, io)),
+            Compound::For(clause) => self.run_loop(|sh| sh.run_for(clause, io)),
+            Compound::Case(clause) => self.run_case(clause, io),
+            // subshells have already been forked off by the time they get here
+            Compound::Subshell(list) | Compound::Group(list) => self.run_list(list, io),
+        }
+    }
+
+    fn run_if(&mut self, clause: &If, io: &Io) -> Result<i32, Box<dyn Error>> {
+        for (condition, body) in &clause.branches {
+            let status = self.run_list(condition, io)?;
+            if self.interrupted() {
+                return Ok(status);
+            }
+            if status == 0 {
+                return self.run_list(body, io);
+            }
+        }
+        match &clause.otherwise {
+            Some(otherwise) => self.run_list(otherwise, io),
+            None => Ok(0),
+        }
+    }
+
+    /// Run a loop, keeping track of how many loops `break` and `continue` can leave
+    fn run_loop(
+        &mut self,
+        run: impl FnOnce(&mut Executor) -> Result<i32, Box<dyn Error>>,
+    ) -> Result<i32, Box<dyn Error>> {
+        self.loops += 1;
+        let status = run(self);
+        self.loops -= 1;
+        status
+    }
+
+    /// Deal with a `break` or `continue` after an iteration, returning whether the loop is over
+    fn end_iteration(&mut self, status: i32) -> bool {
+        match self.flow {
+            Flow::Break(count) => {
+                self.flow = if count > 1 {
+                    Flow::Break(count - 1)
+                } else {
+                    Flow::Normal
+                };
+                true
+            }
+            Flow::Continue(count) if count > 1 => {
+                self.flow = Flow::Continue(count - 1);
+                true
+            }
+            Flow::Continue(_) => {
+                self.flow = Flow::Normal;
+                false
+            }
+            Flow::Return => true,
+            // stop looping if a command was interrupted with ^C
+            Flow::Normal => self.exit || status == 128 + libc::SIGINT,
+        }
+    }
+
+    fn run_while(&mut self, clause: &While, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let mut status = 0;
+        loop {
+            let condition = self.run_list(&clause.condition, io)?;
+            if self.interrupted() {
+                if self.end_iteration(condition) {
+                    break;
+                }
+                continue;
+            }
+            if (condition == 0) == clause.until || condition == 128 + libc::SIGINT {
+                break;
+            }
+            status = self.run_list(&clause.body, io)?;
+            if self.end_iteration(status) {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    fn run_for(&mut self, clause: &For, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let words = match &clause.words {
+            Some(words) => match self.expand_words(words) {
+                Ok(words) => words,
+                Err(err) => {
+                    io.error(err);
+                    return Ok(1);
+                }
+            },
+            None => self.args[1..].to_vec(),
+        };
+        let mut status = 0;
+        for word in words {
+            self.set_var(&clause.name, word);
+            status = self.run_list(&clause.body, io)?;
+            if self.end_iteration(status) {
+                break;
+            }
+        }
+        Ok(status)
+    }
+
+    fn run_case(&mut self, clause: &Case, io: &Io) -> Result<i32, Box<dyn Error>> {
+        let word = match self.expand_word(&clause.word) {
+            Ok(word) => word,
+            Err(err) => {
+                io.error(err);
+                return Ok(1);
+            }
+        };
+        for (patterns, body) in &clause.items {
+            for pattern in patterns {
+                let pattern = match self.expand_pattern(pattern) {
+                    Ok(pattern) => pattern,
+                    Err(err) => {
+                        io.error(err);
+                        return Ok(1);
+                    }
+                };
+                if matches(&pattern, &word) {
+                    return self.run_list(body, io);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    fn run_simple(
+        &mut self,
+        command: &SimpleCommand,
+        mut io: Io,
+        launch: &mut Launch,
+    ) -> Result<Stage, Box<dyn Error>> {
+        if let Some(name) = command.words.first() {
+            if self.aliases.contains_key(name) && !self.expanding.contains(name) {
+                return self.run_alias(name, command, io, launch);
+            }
+        }
+        self.substitution_status = None;
+        let expanded = self
+            .expand_words(&command.words)
+            .and_then(|args| Ok((args, self.expand_assignments(&command.assignments)?)));
+        let (args, assignments) = match expanded {
+            Ok(expanded) => expanded,
+            Err(err) => {
+                io.error(err);
+                return Ok(Stage::Done(1));
+            }
+        };
+        // after expanding, so that `echo $(cat f) > f` reads `f` before it is truncated
+        if !self.redirect(&mut io, &command.redirects)? {
+            return Ok(Stage::Done(1));
+        }
+        if args.is_empty() {
+            for (name, value) in assignments {
+                self.set_var(&name, value);
+            }
+            return Ok(Stage::Done(self.substitution_status.unwrap_or(0)));
+        }
+        if let Some(body) = self.functions.get(&args[0]).cloned() {
+            return self.run_internal(launch, |sh| {
+                sh.with_vars(assignments, |sh| sh.call(&body, &args, &io))
+            });
+        }
+        if is_builtin(&args) {
+            return self.run_internal(launch, |sh| {
+                sh.with_vars(assignments, |sh| sh.run_builtin(&args, &mut io))
+            });
+        }
+        let mut cmd = process::Command::new(&args[0]);
+        cmd.args(&args[1..])
+            .current_dir(&self.path)
+            .env_clear()
+            .envs(self.exported_vars())
+            .envs(assignments)
+            .stdin(io.stdin.try_clone()?)
+            .stdout(io.stdout.try_clone()?)
+            .stderr(io.stderr.try_clone()?);
+        if let Some(control) = self.job_control {
+            let pgid = launch.pgid.unwrap_or(0);
+            let foreground = launch.foreground;
+            unsafe {
+                cmd.pre_exec(move || {
+                    enter_job(control.terminal, pgid, foreground);
+                    Ok(())
+                });
+            }
+        }
+        match cmd.spawn() {
+            Ok(child) => {
+                let pid = child.id() as pid_t;
+                let pgid = *launch.pgid.get_or_insert(pid);
+                if self.job_control.is_some() {
+                    unsafe {
+                        libc::setpgid(pid, pgid);
+                    }
+                }
+                Ok(Stage::Running(pid))
+            }
+            Err(err) => {
+                // the child may have taken the terminal before it failed to exec
+                if let (Some(control), true) = (self.job_control, launch.foreground) {
+                    unsafe {
+                        libc::tcsetpgrp(control.terminal, control.pgid);
+                    }
+                }
+                match err.kind() {
+                    ErrorKind::NotFound => {
+                        io.error(format!("Unknown command: {}", args[0]));
+                        Ok(Stage::Done(127))
+                    }
+                    _ => {
+                        io.error(format!("Error running command: {}", err));
+                        Ok(Stage::Done(126))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_test;
+    use std::{env, fs, path::PathBuf, process};
+
+    #[test]
+    fn runs_pipelines() {
+        let output = run_test("echo a b | tr a-z A-Z | cat");
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout, "A B\n");
+    }
+
+    /// An empty directory for a test to put files in
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("eish-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn redirects_to_and_from_files() {
+        let dir = temp_dir("redirect");
+        let file = dir.join("file");
+        run_test(&format!("echo one > {}", file.display()));
+        run_test(&format!("echo two >> {}", file.display()));
+        assert_eq!(
+            run_test(&format!("cat < {}", file.display())).stdout,
+            "one\ntwo\n"
+        );
+        run_test(&format!("echo three > {}", file.display()));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "three\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn redirects_stderr() {
+        let dir = temp_dir("stderr");
+        let file = dir.join("file");
+        let output = run_test(&format!(
+            "sh -c 'echo out; echo err >&2' 2> {}",
+            file.display()
+        ));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "err\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn duplicates_streams() {
+        let output = run_test("sh -c 'echo out; echo err >&2' 2>&1");
+        assert_eq!(
+            (output.stdout.as_str(), output.stderr.as_str()),
+            ("out\nerr\n", "")
+        );
+        let output = run_test("sh -c 'echo out' >&2");
+        assert_eq!(
+            (output.stdout.as_str(), output.stderr.as_str()),
+            ("", "out\n")
+        );
+        let dir = temp_dir("dup");
+        let file = dir.join("file");
+        for redirects in ["> {} 2>&1", "&> {}"] {
+            let redirects = redirects.replace("{}", &file.display().to_string());
+            let output = run_test(&format!("sh -c 'echo out; echo err >&2' {}", redirects));
+            assert_eq!(output.stdout, "");
+            assert_eq!(fs::read_to_string(&file).unwrap(), "out\nerr\n");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expands_words_before_redirecting() {
+        let dir = temp_dir("expand-first");
+        let file = dir.join("file");
+        let output = run_test(&format!(
+            "echo a > {0}; echo $(cat {0})b > {0}; cat {0}",
+            file.display()
+        ));
+        assert_eq!(output.stdout, "ab\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fails_commands_whose_errors_cant_be_written() {
+        let output = run_test(
+            "nosuchcmd 2>/dev/full; echo $?
+            echo hi 2>/dev/full >/nonexistent/file; echo $?
+            { echo hi; } 2>/dev/full >/nonexistent/file; echo $?",
+        );
+        assert_eq!(output.stdout, "127\n1\n1\n");
+    }
+
+    #[test]
+    fn runs_lists() {
+        let output = run_test("false && echo no; true && echo yes; false || echo or; echo last");
+        assert_eq!(output.stdout, "yes\nor\nlast\n");
+    }
+
+    #[test]
+    fn runs_control_flow() {
+        let output = run_test(
+            "if false; then echo a; elif true; then echo b; else echo c; fi
+            for x in 1 2 3; do if [ $x = 2 ]; then continue; fi; echo $x; done
+            i=0; while true; do i=$((i + 1)); if [ $i -ge 3 ]; then break; fi; done; echo $i
+            until [ $i -eq 0 ]; do i=$((i - 1)); done; echo $i
+            case foo.rs in *.txt) echo text;; *.rs | *.c) echo code;; esac",
+        );
+        assert_eq!(output.stdout, "b\n1\n3\n3\n0\ncode\n");
+    }
+
+    #[test]
+    fn runs_functions() {
+        let output = run_test(
+            "greet() { echo hello $1; }; greet world
+            f() { local x=in; echo $# $1 $2 $x; return 3; echo unreachable; }
+            x=out; f a b; echo $? $x",
+        );
+        assert_eq!(output.stdout, "hello world\n2 a b in\n3 out\n");
+    }
+
+    #[test]
+    fn expands_aliases() {
+        let output =
+            run_test("alias hi='echo hello' echo='echo wrapped'; hi there; echo x; unalias hi; hi");
+        assert_eq!(output.stdout, "wrapped hello there\nwrapped x\n");
+        assert_eq!(output.status, 127);
+    }
+
+    #[test]
+    fn stops_recursive_aliases() {
+        let output = run_test("alias a=b b=a; a");
+        assert_eq!(output.status, 127);
+    }
+
+    #[test]
+    fn runs_subshells_and_groups() {
+        let output = run_test(
+            "{ echo grouped; } | cat; (exit 3) || echo $?
+            x=1; (x=2; echo $x); echo $x; { x=3; }; echo $x",
+        );
+        assert_eq!(output.stdout, "grouped\n3\n2\n1\n3\n");
+    }
+
+    #[test]
+    fn substitutes_processes() {
+        let output = run_test(
+            "cat <(echo hi); diff <(echo a) <(echo a) && echo same; echo $(cat <(printf x))",
+        );
+        assert_eq!(output.stdout, "hi\nsame\nx\n");
+    }
+
+    #[test]
+    fn reads_here_documents() {
+        let output = run_test(
+            "x=v
+cat <<EOF
+a $x
+EOF
+cat <<'EOF'
+b $x
+EOF
+cat <<-EOF
+\t\tc
+\tEOF
+cat <<< \"d $x\"",
+        );
+        assert_eq!(output.stdout, "a v\nb $x\nc\nd v\n");
+    }
+}
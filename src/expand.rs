@@ -0,0 +1,496 @@
+use crate::{
+    arith::ArithError,
+    brace::expand_braces,
+    glob::{escape, glob},
+    lexer::substitution_end,
+    parser::ParseError,
+    vars::{is_name, user_home},
+    Executor,
+};
+use std::{error::Error, fmt::Display, io, mem};
+
+#[derive(Debug)]
+pub enum ExpandError {
+    BadSubstitution(String),
+    NoMatch(String),
+    /// An arithmetic expression which could not be evaluated
+    Arith(String, ArithError),
+    Parse(ParseError),
+    Io(io::Error),
+}
+
+impl Display for ExpandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpandError::BadSubstitution(expansion) => {
+                write!(f, "{}: bad substitution", expansion)
+            }
+            ExpandError::NoMatch(pattern) => write!(f, "no matches found: {}", pattern),
+            ExpandError::Arith(expr, err) => write!(f, "{}: {}", expr, err),
+            ExpandError::Parse(err) => write!(f, "{}", err),
+            ExpandError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ExpandError {}
+
+impl From<ParseError> for ExpandError {
+    fn from(err: ParseError) -> Self {
+        ExpandError::Parse(err)
+    }
+}
+
+impl From<io::Error> for ExpandError {
+    fn from(err: io::Error) -> Self {
+        ExpandError::Io(err)
+    }
+}
+
+/// What to do with a glob which matches no files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoMatch {
+    /// Fail the command
+    Error,
+    /// Pass the pattern on as it is
+    Pass,
+    /// Remove the word
+    Null,
+}
+
+/// A field words expand to
+#[derive(Debug)]
+struct Field {
+    text: String,
+    /// The field as a glob pattern, if it has unquoted glob characters
+    pattern: Option<String>,
+}
+
+/// The fields words expand to, built up a piece at a time
+#[derive(Debug, Default)]
+struct Fields {
+    fields: Vec<Field>,
+    current: String,
+    /// The current field with the characters that came from quotes escaped
+    pattern: String,
+    /// Whether the current field has unquoted glob characters
+    glob: bool,
+    /// Whether the current field exists even if it is empty, like after `""`
+    started: bool,
+}
+
+impl Fields {
+    fn push(&mut self, chr: char, quoted: bool) {
+        let mut buf = [0; 4];
+        self.push_str(chr.encode_utf8(&mut buf), quoted);
+    }
+
+    fn push_str(&mut self, text: &str, quoted: bool) {
+        self.current.push_str(text);
+        if quoted {
+            self.pattern.push_str(&escape(text));
+        } else {
+            self.pattern.push_str(text);
+            self.glob |= text.contains(['*', '?', '[']);
+        }
+        self.started = true;
+    }
+
+    /// Add the result of an unquoted expansion, splitting it into fields on whitespace
+    fn push_split(&mut self, text: &str) {
+        for (i, piece) in text.split(char::is_whitespace).enumerate() {
+            if i != 0 {
+                self.finish();
+            }
+            if !piece.is_empty() {
+                self.push_str(piece, false);
+            }
+        }
+    }
+
+    /// End the current field
+    fn finish(&mut self) {
+        if self.started {
+            let pattern = mem::take(&mut self.pattern);
+            self.fields.push(Field {
+                text: mem::take(&mut self.current),
+                pattern: mem::take(&mut self.glob).then_some(pattern),
+            });
+            self.started = false;
+        }
+    }
+}
+
+impl Executor {
+    /// Expand raw words into the arguments they stand for
+    pub fn expand_words(&mut self, words: &[String]) -> Result<Vec<String>, ExpandError> {
+        let mut fields = Fields::default();
+        for word in words.iter().flat_map(|word| expand_braces(word)) {
+            self.expand_into(&word, &mut fields, true)?;
+            fields.finish();
+        }
+        let mut args = Vec::new();
+        for field in fields.fields {
+            let pattern = match field.pattern {
+                Some(pattern) => pattern,
+                None => {
+                    args.push(field.text);
+                    continue;
+                }
+            };
+            let paths = glob(&pattern, &self.path);
+            if !paths.is_empty() {
+                args.extend(paths);
+                continue;
+            }
+            match self.no_match() {
+                NoMatch::Error => return Err(ExpandError::NoMatch(field.text)),
+                NoMatch::Pass => args.push(field.text),
+                NoMatch::Null => {}
+            }
+        }
+        Ok(args)
+    }
+
+    /// What to do with globs which match nothing, set with `EISH_NOMATCH=error|pass|null`
+    fn no_match(&self) -> NoMatch {
+        match self.get_var("EISH_NOMATCH").as_deref() {
+            Some("error") => NoMatch::Error,
+            Some("null") => NoMatch::Null,
+            _ => NoMatch::Pass,
+        }
+    }
+
+    /// Expand a raw word into a single string without splitting it, removing quotes on the way
+    pub fn expand_word(&mut self, word: &str) -> Result<String, ExpandError> {
+        let mut fields = Fields::default();
+        self.expand_into(word, &mut fields, false)?;
+        fields.finish();
+        let fields: Vec<String> = fields.fields.into_iter().map(|field| field.text).collect();
+        Ok(fields.join(" "))
+    }
+
+    fn expand_into(
+        &mut self,
+        word: &str,
+        fields: &mut Fields,
+        split: bool,
+    ) -> Result<(), ExpandError> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut i = self.expand_tilde(&chars, fields);
+        while let Some(&chr) = chars.get(i) {
+            i += 1;
+            match chr {
+                '\\' => {
+                    if let Some(&chr) = chars.get(i) {
+                        fields.push(chr, true);
+                        i += 1;
+                    }
+                }
+                '\'' => {
+                    fields.started = true;
+                    while let Some(&chr) = chars.get(i) {
+                        i += 1;
+                        if chr == '\'' {
+                            break;
+                        }
+                        fields.push(chr, true);
+                    }
+                }
+                '"' => {
+                    if chars[i..].starts_with(&['$', '@', '"']) {
+                        // "$@" stands for each positional parameter as a field of its own
+                        i += 3;
+                        for (n, arg) in self.args[1..].iter().enumerate() {
+                            if n != 0 {
+                                fields.finish();
+                            }
+                            fields.push_str(arg, true);
+                        }
+                        continue;
+                    }
+                    fields.started = true;
+                    while let Some(&chr) = chars.get(i) {
+                        i += 1;
+                        match chr {
+                            '"' => break,
+                            '\\' => match chars.get(i) {
+                                Some(&chr @ ('"' | '\\' | '$' | '`')) => {
+                                    fields.push(chr, true);
+                                    i += 1;
+                                }
+                                _ => fields.push('\\', true),
+                            },
+                            '$' => match self.expand_dollar(&chars, &mut i)? {
+                                Some(value) => fields.push_str(&value, true),
+                                None => fields.push('$', true),
+                            },
+                            '`' => {
+                                let value = self.expand_backquote(&chars, &mut i, true)?;
+                                fields.push_str(&value, true);
+                            }
+                            chr => fields.push(chr, true),
+                        }
+                    }
+                }
+                '$' => match self.expand_dollar(&chars, &mut i)? {
+                    Some(value) if split => fields.push_split(&value),
+                    Some(value) => fields.push_str(&value, false),
+                    None => fields.push('$', false),
+                },
+                '`' => {
+                    let value = self.expand_backquote(&chars, &mut i, false)?;
+                    if split {
+                        fields.push_split(&value);
+                    } else {
+                        fields.push_str(&value, false);
+                    }
+                }
+                '<' | '>' if chars.get(i) == Some(&'(') => {
+                    let end = substitution_end(&chars, i + 1).ok_or_else(|| {
+                        ExpandError::BadSubstitution(chars[i - 1..].iter().collect())
+                    })?;
+                    let command: String = chars[i + 1..end - 1].iter().collect();
+                    i = end;
+                    let path = self.substitute_process(&command, chr == '<')?;
+                    fields.push_str(&path, true);
+                }
+                chr => fields.push(chr, false),
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the body of a here-document, where only `$`, `` ` `` and `\` mean anything
+    pub fn expand_here_doc(&mut self, body: &str) -> Result<String, ExpandError> {
+        self.expand_text(body, false)
+    }
+
+    /// Expand an arithmetic expression like the body of a here-document, except that double
+    /// quotes are removed. Nothing else is special, so `1<(2)` is a comparison.
+    pub fn expand_arith(&mut self, expr: &str) -> Result<String, ExpandError> {
+        self.expand_text(expr, true)
+    }
+
+    /// Expand text where only `$`, `` ` `` and `\` mean anything, removing any `"` if `quotes`
+    fn expand_text(&mut self, raw: &str, quotes: bool) -> Result<String, ExpandError> {
+        let chars: Vec<char> = raw.chars().collect();
+        let mut text = String::new();
+        let mut i = 0;
+        while let Some(&chr) = chars.get(i) {
+            i += 1;
+            match chr {
+                '\\' => match chars.get(i) {
+                    Some(&chr @ ('$' | '`' | '\\')) => {
+                        text.push(chr);
+                        i += 1;
+                    }
+                    Some('\n') => i += 1,
+                    _ => text.push('\\'),
+                },
+                '$' => match self.expand_dollar(&chars, &mut i)? {
+                    Some(value) => text.push_str(&value),
+                    None => text.push('$'),
+                },
+                '`' => {
+                    let value = self.expand_backquote(&chars, &mut i, false)?;
+                    text.push_str(&value);
+                }
+                '"' if quotes => {}
+                chr => text.push(chr),
+            }
+        }
+        Ok(text)
+    }
+
+    /// Expand a raw word into a pattern to match against, with anything that was quoted escaped
+    pub fn expand_pattern(&mut self, word: &str) -> Result<String, ExpandError> {
+        let mut fields = Fields::default();
+        self.expand_into(word, &mut fields, false)?;
+        fields.finish();
+        Ok(fields
+            .fields
+            .into_iter()
+            .next()
+            .map(|field| field.pattern.unwrap_or_else(|| escape(&field.text)))
+            .unwrap_or_default())
+    }
+
+    /// Expand a `~` at the start of a word, returning where the rest of the word starts
+    fn expand_tilde(&self, chars: &[char], fields: &mut Fields) -> usize {
+        if chars.first() != Some(&'~') {
+            return 0;
+        }
+        let end = chars
+            .iter()
+            .position(|chr| *chr == '/')
+            .unwrap_or(chars.len());
+        let prefix: String = chars[1..end].iter().collect();
+        if prefix.contains(['\\', '\'', '"', '$', '`']) {
+            return 0;
+        }
+        let home = match prefix.as_str() {
+            "" => self.home(),
+            "+" => self.get_var("PWD"),
+            "-" => self.get_var("OLDPWD"),
+            user => user_home(Some(user)),
+        };
+        match home {
+            Some(home) => {
+                fields.push_str(&home, true);
+                end
+            }
+            None => 0,
+        }
+    }
+
+    /// Run the command in a `` `...` `` substitution whose contents start at `i`
+    fn expand_backquote(
+        &mut self,
+        chars: &[char],
+        i: &mut usize,
+        quoted: bool,
+    ) -> Result<String, ExpandError> {
+        let mut command = String::new();
+        while let Some(&chr) = chars.get(*i) {
+            *i += 1;
+            match chr {
+                '`' => break,
+                '\\' => match chars.get(*i) {
+                    Some(&chr @ ('`' | '\\' | '$')) => {
+                        command.push(chr);
+                        *i += 1;
+                    }
+                    Some('"') if quoted => {
+                        command.push('"');
+                        *i += 1;
+                    }
+                    _ => command.push('\\'),
+                },
+                chr => command.push(chr),
+            }
+        }
+        self.capture(&command)
+    }
+
+    /// Expand whatever follows a `$`, returning `None` if it is just a literal `$`
+    fn expand_dollar(
+        &mut self,
+        chars: &[char],
+        i: &mut usize,
+    ) -> Result<Option<String>, ExpandError> {
+        match chars.get(*i) {
+            Some('(') => {
+                let end = substitution_end(chars, *i + 1).ok_or_else(|| {
+                    ExpandError::BadSubstitution(chars[*i - 1..].iter().collect())
+                })?;
+                let start = *i + 1;
+                *i = end;
+                // `$((...))` is arithmetic as long as its inner parentheses are a single pair
+                if chars.get(start) == Some(&'(')
+                    && substitution_end(chars, start + 1) == Some(end - 1)
+                {
+                    let expr: String = chars[start + 1..end - 2].iter().collect();
+                    let expr = self.expand_arith(&expr)?;
+                    let value = self
+                        .arith(&expr)
+                        .map_err(|err| ExpandError::Arith(expr, err))?;
+                    return Ok(Some(value.to_string()));
+                }
+                let command: String = chars[start..end - 1].iter().collect();
+                Ok(Some(self.capture(&command)?))
+            }
+            Some('{') => {
+                let start = *i + 1;
+                let mut end = start;
+                while chars.get(end).is_some_and(|chr| *chr != '}') {
+                    end += 1;
+                }
+                *i = end + 1;
+                let name: String = chars[start..end].iter().collect();
+                let digits = !name.is_empty() && name.chars().all(|chr| chr.is_ascii_digit());
+                if !is_name(&name) && !is_special(&name) && !digits {
+                    return Err(ExpandError::BadSubstitution(format!("${{{}}}", name)));
+                }
+                Ok(Some(self.get_var(&name).unwrap_or_default()))
+            }
+            Some(&chr) if is_special(&chr.to_string()) || chr.is_ascii_digit() => {
+                *i += 1;
+                Ok(Some(self.get_var(&chr.to_string()).unwrap_or_default()))
+            }
+            Some(&chr) if chr == '_' || chr.is_ascii_alphabetic() => {
+                let start = *i;
+                while chars
+                    .get(*i)
+                    .is_some_and(|chr| *chr == '_' || chr.is_ascii_alphanumeric())
+                {
+                    *i += 1;
+                }
+                let name: String = chars[start..*i].iter().collect();
+                Ok(Some(self.get_var(&name).unwrap_or_default()))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Whether a name is one of the special parameters like `$?`
+fn is_special(name: &str) -> bool {
+    matches!(name, "?" | "!" | "$" | "#" | "@" | "*")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_test;
+
+    #[test]
+    fn removes_quotes() {
+        let output = run_test(r#"echo 'a  b' "c \" \d" e\ f"#);
+        assert_eq!(output.stdout, "a  b c \" \\d e f\n");
+    }
+
+    #[test]
+    fn expands_the_status() {
+        let output = run_test("false; echo $?; echo $?");
+        assert_eq!(output.stdout, "1\n0\n");
+    }
+
+    #[test]
+    fn expands_variables() {
+        let output = run_test("x='a  b'; echo $x \"$x\" ${x}c $unset. '$x' \\$x");
+        assert_eq!(output.stdout, "a b a  b a bc . $x $x\n");
+    }
+
+    #[test]
+    fn substitutes_commands() {
+        let output = run_test(
+            "echo $(echo a; echo b) `echo c` \"$(printf 'd\\n\\n')\" $(echo $(echo nested))",
+        );
+        assert_eq!(output.stdout, "a b c d nested\n");
+    }
+
+    #[test]
+    fn expands_tildes() {
+        let output =
+            run_test("HOME=/home/test PWD=/p OLDPWD=/o; echo ~ ~/dir '~' a~ ~+ ~- ~root x=~");
+        assert_eq!(
+            output.stdout,
+            "/home/test /home/test/dir ~ a~ /p /o /root x=~\n"
+        );
+    }
+
+    #[test]
+    fn ends_assignments_with_the_status_of_substitutions() {
+        let output = run_test("x=$(exit 3); echo $?; x=$(false) || echo fired; x=1; echo $?");
+        assert_eq!(output.stdout, "3\nfired\n0\n");
+    }
+
+    #[test]
+    fn collects_stderr_of_substitutions() {
+        let output = run_test(
+            "x=$(echo oops >&2); echo $?; f() { echo $(ls /nonexistent); }; f 2>/dev/null",
+        );
+        assert_eq!(output.stdout, "0\n\n");
+        assert_eq!(output.stderr, "oops\n");
+    }
+}
@@ -0,0 +1,240 @@
+use std::{fs, path::Path};
+
+/// Whether a word has unescaped `*`, `?` or `[` in it
+pub fn has_glob(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Escape the characters of a literal string which mean something in a pattern
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for chr in text.chars() {
+        if matches!(chr, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(chr);
+    }
+    escaped
+}
+
+/// Remove the backslashes from a pattern without special characters
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => text.extend(chars.next()),
+            chr => text.push(chr),
+        }
+    }
+    text
+}
+
+/// Whether a name matches a pattern like `*.rs`
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // the last `*` and the position in the name it is currently matching up to
+    let mut backtrack = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match bracket(&pattern[p..], name[n]) {
+                Some((matched, len)) => matched.then_some(len),
+                None => (name[n] == '[').then_some(1),
+            },
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == name[n]).then_some(2),
+            Some(&chr) => (chr == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((star, start))) => {
+                backtrack = Some((star, start + 1));
+                p = star + 1;
+                n = start + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|chr| *chr == '*')
+}
+
+/// Match a character against the `[...]` class at the start of a pattern,
+/// returning whether it matched and how long the class is
+fn bracket(pattern: &[char], chr: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(i)?;
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if start == '\\' {
+            i += 1;
+            start = *pattern.get(i)?;
+        }
+        i += 1;
+        let mut end = start;
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|chr| *chr != ']') {
+            end = pattern[i + 1];
+            i += 2;
+            if end == '\\' {
+                end = *pattern.get(i)?;
+                i += 1;
+            }
+        }
+        if start <= chr && chr <= end {
+            matched = true;
+        }
+    }
+}
+
+/// Find the paths matching a pattern, relative to `dir` unless the pattern is absolute.
+///
+/// A `**` component matches any number of directories.
+pub fn glob(pattern: &str, dir: &Path) -> Vec<String> {
+    let (prefix, pattern) = match pattern.strip_prefix('/') {
+        Some(pattern) => ("/", pattern),
+        None => ("", pattern),
+    };
+    let components: Vec<&str> = pattern.split('/').collect();
+    let mut paths = Vec::new();
+    walk(dir, prefix.to_string(), &components, &mut paths);
+    paths.sort();
+    paths
+}
+
+/// Add the paths under `prefix` which match the remaining components
+fn walk(dir: &Path, prefix: String, components: &[&str], paths: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => return paths.push(prefix),
+    };
+    let path = dir.join(&prefix);
+    if component.is_empty() {
+        // a trailing or doubled slash only matches directories
+        if path.is_dir() {
+            let prefix = if prefix.ends_with('/') {
+                prefix
+            } else {
+                prefix + "/"
+            };
+            walk(dir, prefix, rest, paths);
+        }
+        return;
+    }
+    if !has_glob(component) {
+        let name = unescape(component);
+        if rest.is_empty() && fs::symlink_metadata(path.join(&name)).is_err() {
+            return;
+        }
+        return walk(dir, join(&prefix, &name, rest), rest, paths);
+    }
+    let entries = match fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+        .collect();
+    names.sort();
+    if *component == "**" {
+        let rest = if rest.is_empty() { &["*"][..] } else { rest };
+        walk(dir, prefix.clone(), rest, paths);
+        for name in names {
+            let is_dir = fs::symlink_metadata(path.join(&name)).is_ok_and(|meta| meta.is_dir());
+            if is_dir {
+                walk(dir, format!("{}{}/", prefix, name), components, paths);
+            }
+        }
+        return;
+    }
+    for name in names {
+        if matches(component, &name) && (rest.is_empty() || path.join(&name).is_dir()) {
+            walk(dir, join(&prefix, &name, rest), rest, paths);
+        }
+    }
+}
+
+/// Add a name to a path prefix, with a slash if more components follow
+fn join(prefix: &str, name: &str, rest: &[&str]) -> String {
+    if rest.is_empty() || rest == [""] {
+        format!("{}{}", prefix, name)
+    } else {
+        format!("{}{}/", prefix, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_test;
+    use std::{env, fs, process};
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("?[a-c]", "xb"));
+        assert!(matches("[!a-c]*", "pear"));
+        assert!(!matches("[!a-c]*", "apple"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "a"));
+    }
+
+    #[test]
+    fn expands_paths() {
+        let dir = env::temp_dir().join(format!("eish-glob-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub/deep")).unwrap();
+        for name in [
+            "a.txt",
+            "b.txt",
+            "c.md",
+            ".hidden.txt",
+            "sub/d.txt",
+            "sub/deep/e.txt",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let output = run_test(&format!(
+            "echo {0}/*.txt; echo {0}/*/*.txt; echo {0}/?.md {0}/[ab].txt; echo '{0}/*.txt' {0}/*.none; echo {0}/**/*.txt",
+            dir.display()
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            output.stdout,
+            format!(
+                "{0}/a.txt {0}/b.txt\n{0}/sub/d.txt\n{0}/c.md {0}/a.txt {0}/b.txt\n{0}/*.txt {0}/*.none\n{0}/a.txt {0}/b.txt {0}/sub/d.txt {0}/sub/deep/e.txt\n",
+                dir.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{
+    exec::{signal_name, status_code},
+    Executor,
+};
+use libc::{pid_t, termios};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    mem,
+    os::{fd::RawFd, unix::process::ExitStatusExt},
+    process::ExitStatus,
+};
+
+/// Signals the shell ignores while it manages the terminal
+const JOB_SIGNALS: [i32; 4] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU, libc::SIGQUIT];
+
+/// State the shell keeps while it is in charge of the terminal
+#[derive(Debug, Clone, Copy)]
+pub struct JobControl {
+    /// A private copy of the controlling terminal's file descriptor
+    pub terminal: RawFd,
+    /// The shell's own process group
+    pub pgid: pid_t,
+    /// The terminal modes the shell runs with
+    pub tmodes: termios,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+impl Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(0) => write!(f, "Done"),
+            JobState::Done(status) => match signal_name(status - 128) {
+                Some(name) => write!(f, "Killed ({})", name),
+                None => write!(f, "Exit {}", status),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: pid_t,
+    pub status: Option<i32>,
+    pub stopped: bool,
+}
+
+impl Process {
+    /// Record a status reported by `waitpid`
+    fn update(&mut self, status: i32) {
+        if libc::WIFSTOPPED(status) {
+            self.stopped = true;
+        } else if libc::WIFCONTINUED(status) {
+            self.stopped = false;
+        } else {
+            self.status = Some(status_code(ExitStatus::from_raw(status)));
+        }
+    }
+}
+
+/// A pipeline the shell started, running in its own process group
+#[derive(Debug)]
+pub struct Job {
+    /// The number used to refer to the job with `%n`, 0 until it is added to the job table
+    pub id: usize,
+    pub pgid: pid_t,
+    pub processes: Vec<Process>,
+    pub command: String,
+    /// The terminal modes the job had when it was stopped
+    pub tmodes: Option<termios>,
+    /// The state the user was last told the job is in
+    pub notified: JobState,
+}
+
+impl Job {
+    pub fn new(command: String, pgid: pid_t, pids: Vec<pid_t>) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            processes: pids
+                .into_iter()
+                .map(|pid| Process {
+                    pid,
+                    status: None,
+                    stopped: false,
+                })
+                .collect(),
+            command,
+            tmodes: None,
+            notified: JobState::Running,
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        if self
+            .processes
+            .iter()
+            .all(|process| process.status.is_some())
+        {
+            // jobs are never created without processes
+            JobState::Done(self.processes.last().unwrap().status.unwrap())
+        } else if self
+            .processes
+            .iter()
+            .any(|process| process.status.is_none() && process.stopped)
+        {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        }
+    }
+
+    /// Block until every process of the job finished or one of them was stopped
+    fn wait(&mut self) {
+        for process in self.processes.iter_mut() {
+            while process.status.is_none() {
+                let mut status = 0;
+                if unsafe { libc::waitpid(process.pid, &mut status, libc::WUNTRACED) } == -1 {
+                    if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    // someone else already reaped it, there is nothing left to wait for
+                    process.status = Some(0);
+                    break;
+                }
+                process.update(status);
+                if process.stopped {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Check on the job's processes without blocking
+    fn poll(&mut self) {
+        for process in self.processes.iter_mut() {
+            if process.status.is_some() {
+                continue;
+            }
+            let mut status = 0;
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            match unsafe { libc::waitpid(process.pid, &mut status, flags) } {
+                0 => {}
+                -1 => process.status = Some(0),
+                _ => process.update(status),
+            }
+        }
+    }
+
+    /// A line describing the job like `[1]+  Stopped    vim`
+    pub fn describe(&self, current: bool) -> String {
+        format!(
+            "[{}]{}  {:<24}{}",
+            self.id,
+            if current { "+" } else { " " },
+            self.state().to_string(),
+            self.command
+        )
+    }
+
+    /// Send SIGCONT to every process of the job
+    pub fn resume(&mut self) {
+        for process in self.processes.iter_mut() {
+            process.stopped = false;
+        }
+        unsafe {
+            libc::kill(-self.pgid, libc::SIGCONT);
+        }
+    }
+}
+
+/// Wait for a process which is not part of a job, returning its status
+pub fn wait_pid(pid: pid_t) -> i32 {
+    let mut process = Process {
+        pid,
+        status: None,
+        stopped: false,
+    };
+    while process.status.is_none() {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return 0;
+        }
+        process.update(status);
+    }
+    process.status.unwrap_or_default()
+}
+
+/// Move a freshly forked child into its job's process group.
+///
+/// This only makes async-signal-safe calls so it can run between `fork` and `exec`.
+pub fn enter_job(terminal: RawFd, pgid: pid_t, foreground: bool) {
+    unsafe {
+        libc::setpgid(0, pgid);
+        if foreground {
+            libc::tcsetpgrp(terminal, libc::getpgrp());
+        }
+    }
+    reset_job_signals();
+}
+
+/// Stop ignoring the signals the shell ignores while it manages the terminal, in a child which
+/// is about to run something else
+pub fn reset_job_signals() {
+    for signal in JOB_SIGNALS {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+}
+
+impl Executor {
+    /// Take control of the terminal if the shell is running interactively
+    pub fn init_job_control(&mut self) -> io::Result<()> {
+        let terminal = libc::STDIN_FILENO;
+        unsafe {
+            if libc::isatty(terminal) == 0 {
+                return Ok(());
+            }
+            // wait until we have been put in the foreground
+            loop {
+                let pgid = libc::getpgrp();
+                if libc::tcgetpgrp(terminal) == pgid {
+                    break;
+                }
+                libc::kill(-pgid, libc::SIGTTIN);
+            }
+            for signal in JOB_SIGNALS {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+            let pgid = libc::getpid();
+            // this fails harmlessly if we already lead a session
+            libc::setpgid(pgid, pgid);
+            let pgid = libc::getpgrp();
+            if libc::tcsetpgrp(terminal, pgid) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let terminal = libc::fcntl(terminal, libc::F_DUPFD_CLOEXEC, 10);
+            if terminal == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut tmodes = mem::zeroed();
+            libc::tcgetattr(terminal, &mut tmodes);
+            self.job_control = Some(JobControl {
+                terminal,
+                pgid,
+                tmodes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add a job to the job table, returning its id
+    pub fn add_job(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let idx = self
+            .jobs
+            .iter()
+            .position(|other| other.id > id)
+            .unwrap_or(self.jobs.len());
+        self.jobs.insert(idx, job);
+        id
+    }
+
+    /// Give a job the terminal and wait for it to finish or stop, returning its status
+    pub fn wait_for_job(&mut self, mut job: Job) -> io::Result<i32> {
+        if let Some(control) = self.job_control {
+            unsafe {
+                if let Some(tmodes) = job.tmodes {
+                    libc::tcsetattr(control.terminal, libc::TCSADRAIN, &tmodes);
+                }
+                libc::tcsetpgrp(control.terminal, job.pgid);
+            }
+        }
+        job.wait();
+        if let Some(control) = self.job_control {
+            unsafe {
+                libc::tcsetpgrp(control.terminal, control.pgid);
+                let mut tmodes = mem::zeroed();
+                libc::tcgetattr(control.terminal, &mut tmodes);
+                job.tmodes = Some(tmodes);
+                libc::tcsetattr(control.terminal, libc::TCSADRAIN, &control.tmodes);
+            }
+        }
+        match job.state() {
+            JobState::Stopped => {
+                job.notified = JobState::Stopped;
+                let id = self.add_job(job);
+                self.report_job(id)?;
+                Ok(128 + libc::SIGTSTP)
+            }
+            JobState::Done(status) => Ok(status),
+            JobState::Running => Ok(0),
+        }
+    }
+
+    /// The ids of the current and previous jobs, `%+` and `%-`
+    pub fn current_jobs(&self) -> (Option<usize>, Option<usize>) {
+        let mut ids: Vec<(bool, usize)> = self
+            .jobs
+            .iter()
+            .map(|job| (job.state() == JobState::Stopped, job.id))
+            .collect();
+        ids.sort();
+        let mut ids = ids.into_iter().rev().map(|(_, id)| id);
+        (ids.next(), ids.next())
+    }
+
+    /// Find the index of a job in the job table from a job spec like `%2`
+    pub fn find_job(&self, spec: Option<&str>) -> Result<usize, String> {
+        let (current, previous) = self.current_jobs();
+        let id = match spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec)) {
+            None | Some("" | "%" | "+") => current,
+            Some("-") => previous,
+            Some(spec) => match spec.parse::<usize>() {
+                Ok(id) => Some(id),
+                Err(_) => self
+                    .jobs
+                    .iter()
+                    .find(|job| job.command.starts_with(spec))
+                    .map(|job| job.id),
+            },
+        };
+        id.and_then(|id| self.jobs.iter().position(|job| job.id == id))
+            .ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
+
+    /// Tell the user about the state of a job
+    fn report_job(&self, id: usize) -> io::Result<()> {
+        let (current, _) = self.current_jobs();
+        if let Some(job) = self.jobs.iter().find(|job| job.id == id) {
+            writeln!(io::stderr(), "{}", job.describe(current == Some(id)))?;
+        }
+        Ok(())
+    }
+
+    /// Check on every background job without blocking
+    pub fn poll_jobs(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.poll();
+        }
+        self.reap_substitutions();
+    }
+
+    /// Tell the user about jobs which finished or stopped, forgetting the finished ones
+    pub fn notify_jobs(&mut self) -> io::Result<()> {
+        self.poll_jobs();
+        for job in &self.jobs {
+            let state = job.state();
+            if state != job.notified && state != JobState::Running {
+                self.report_job(job.id)?;
+            }
+        }
+        for job in self.jobs.iter_mut() {
+            job.notified = job.state();
+        }
+        self.jobs
+            .retain(|job| !matches!(job.state(), JobState::Done(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use std::{error::Error, fmt::Display, mem};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A word exactly as it was typed, quotes and escapes included
+    Word(String),
+    Pipe,
+    And,
+    Or,
+    Semi,
+    /// `;;`, which ends an item of a `case` command
+    DoubleSemi,
+    Amp,
+    LParen,
+    RParen,
+    Newline,
+    /// A redirection operator with the file descriptor number before it, if any
+    Redirect(Option<i32>, RedirectOp),
+    /// The raw expression of a `((...))` arithmetic command
+    Arith(String),
+    /// The delimiter of a here-document as it was typed, with the body read from the lines
+    /// after it
+    HereDoc(String, HereDoc),
+}
+
+/// The contents of a here-document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HereDoc {
+    pub body: String,
+    /// Whether the body has expansions in it, which it doesn't if the delimiter was quoted
+    pub expand: bool,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Pipe => write!(f, "|"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Semi => write!(f, ";"),
+            Token::DoubleSemi => write!(f, ";;"),
+            Token::Amp => write!(f, "&"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Newline => write!(f, "newline"),
+            Token::Redirect(Some(fd), op) => write!(f, "{}{}", fd, op),
+            Token::Redirect(None, op) => write!(f, "{}", op),
+            Token::Arith(expr) => write!(f, "(({}))", expr),
+            Token::HereDoc(delimiter, _) => write!(f, "{}", delimiter),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `<&`
+    DupRead,
+    /// `>&`
+    DupWrite,
+    /// `&>`
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+    /// `<<`
+    HereDoc,
+    /// `<<-`, which strips leading tabs from the here-document
+    HereDocStrip,
+    /// `<<<`
+    HereString,
+}
+
+impl Display for RedirectOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RedirectOp::Read => "<",
+                RedirectOp::Write => ">",
+                RedirectOp::Append => ">>",
+                RedirectOp::DupRead => "<&",
+                RedirectOp::DupWrite => ">&",
+                RedirectOp::WriteAll => "&>",
+                RedirectOp::AppendAll => "&>>",
+                RedirectOp::HereDoc => "<<",
+                RedirectOp::HereDocStrip => "<<-",
+                RedirectOp::HereString => "<<<",
+            }
+        )
+    }
+}
+
+/// Whether a character ends an unquoted word
+fn is_meta(chr: char) -> bool {
+    chr.is_whitespace() || matches!(chr, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    UnterminatedQuote(char),
+    UnterminatedExpansion(&'static str),
+    /// A here-document whose delimiter never came
+    MissingDelimiter(String),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexError::UnterminatedQuote(quote) => write!(f, "unterminated quote {}", quote),
+            LexError::UnterminatedExpansion(start) => {
+                write!(f, "unterminated expansion {}", start)
+            }
+            LexError::MissingDelimiter(delimiter) => {
+                write!(f, "here-document not delimited by {}", delimiter)
+            }
+        }
+    }
+}
+
+impl Error for LexError {}
+
+pub struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    /// Where the here-documents whose bodies haven't been read yet are in the tokens, and
+    /// whether they strip tabs
+    here_docs: Vec<(usize, bool)>,
+}
+
+impl Lexer {
+    pub fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            pos: 0,
+            here_docs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let chr = self.peek()?;
+        self.pos += 1;
+        Some(chr)
+    }
+
+    /// Split the input into tokens
+    pub fn tokenize(mut self) -> Result<Vec<Token>, LexError> {
+        let mut tokens = Vec::new();
+        while let Some(chr) = self.peek() {
+            if chr == '\n' {
+                self.pos += 1;
+                tokens.push(Token::Newline);
+                self.here_doc_bodies(&mut tokens)?;
+            } else if chr.is_whitespace() {
+                self.pos += 1;
+            } else if self.eat("\\\n") {
+                // a line continuation
+            } else if chr == '#' || self.lookahead("//") {
+                // a comment, which runs up to the end of the line
+                while self.peek().is_some_and(|chr| chr != '\n') {
+                    self.pos += 1;
+                }
+            } else if self.eat("||") {
+                tokens.push(Token::Or);
+            } else if self.eat("|") {
+                tokens.push(Token::Pipe);
+            } else if self.eat("&&") {
+                tokens.push(Token::And);
+            } else if self.eat(";;") {
+                tokens.push(Token::DoubleSemi);
+            } else if self.eat(";") {
+                tokens.push(Token::Semi);
+            } else if (chr == '<' || chr == '>') && !self.lookahead_substitution()
+                || self.lookahead("&>")
+            {
+                self.redirect(None, &mut tokens)?;
+            } else if self.eat("&") {
+                tokens.push(Token::Amp);
+            } else if let Some(expr) = self.arith() {
+                tokens.push(Token::Arith(expr));
+            } else if self.eat("(") {
+                tokens.push(Token::LParen);
+            } else if self.eat(")") {
+                tokens.push(Token::RParen);
+            } else {
+                let word = self.word()?;
+                if word.chars().all(|chr| chr.is_ascii_digit())
+                    && matches!(self.peek(), Some('<' | '>'))
+                {
+                    self.redirect(word.parse().ok(), &mut tokens)?;
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+        self.here_doc_bodies(&mut tokens)?;
+        Ok(tokens)
+    }
+
+    /// Whether the input continues with the given string
+    fn lookahead(&self, expected: &str) -> bool {
+        let expected: Vec<char> = expected.chars().collect();
+        self.chars[self.pos..].starts_with(&expected)
+    }
+
+    /// Whether a `<(...)` or `>(...)` process substitution comes next
+    fn lookahead_substitution(&self) -> bool {
+        self.lookahead("<(") || self.lookahead(">(")
+    }
+
+    /// Consume the given string if the input continues with it
+    fn eat(&mut self, expected: &str) -> bool {
+        if self.lookahead(expected) {
+            self.pos += expected.chars().count();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume a `((...))` arithmetic command if one comes next, rather than two `(`s opening
+    /// subshells
+    fn arith(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self.eat("((") {
+            return None;
+        }
+        let mut expr = String::new();
+        if self.nested(&mut expr, ')', "((").is_err() || !self.eat(")") {
+            self.pos = start;
+            return None;
+        }
+        // drop the inner closing parenthesis
+        expr.pop();
+        Some(expr)
+    }
+
+    /// Consume a redirection operator, along with the delimiter if it starts a here-document
+    fn redirect(&mut self, fd: Option<i32>, tokens: &mut Vec<Token>) -> Result<(), LexError> {
+        let op = self.redirect_op();
+        tokens.push(Token::Redirect(fd, op));
+        if !matches!(op, RedirectOp::HereDoc | RedirectOp::HereDocStrip) {
+            return Ok(());
+        }
+        while self
+            .peek()
+            .is_some_and(|chr| chr.is_whitespace() && chr != '\n')
+        {
+            self.pos += 1;
+        }
+        if self.peek().is_some_and(|chr| !is_meta(chr)) {
+            let delimiter = self.word()?;
+            self.here_docs
+                .push((tokens.len(), op == RedirectOp::HereDocStrip));
+            tokens.push(Token::HereDoc(delimiter, HereDoc::default()));
+        }
+        Ok(())
+    }
+
+    /// Read the bodies of the here-documents started on the line that just ended
+    fn here_doc_bodies(&mut self, tokens: &mut [Token]) -> Result<(), LexError> {
+        for (index, strip) in mem::take(&mut self.here_docs) {
+            let Token::HereDoc(delimiter, doc) = &mut tokens[index] else {
+                continue;
+            };
+            let end = unquote(delimiter);
+            doc.expand = end == *delimiter;
+            loop {
+                if self.peek().is_none() {
+                    return Err(LexError::MissingDelimiter(end));
+                }
+                let mut line = String::new();
+                while let Some(chr) = self.bump() {
+                    if chr == '\n' {
+                        break;
+                    }
+                    line.push(chr);
+                }
+                let line = if strip {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == end {
+                    break;
+                }
+                doc.body.push_str(line);
+                doc.body.push('\n');
+            }
+        }
+        Ok(())
+    }
+
+    fn redirect_op(&mut self) -> RedirectOp {
+        if self.eat("<<<") {
+            RedirectOp::HereString
+        } else if self.eat("<<-") {
+            RedirectOp::HereDocStrip
+        } else if self.eat("<<") {
+            RedirectOp::HereDoc
+        } else if self.eat("&>>") {
+            RedirectOp::AppendAll
+        } else if self.eat("&>") {
+            RedirectOp::WriteAll
+        } else if self.eat(">>") {
+            RedirectOp::Append
+        } else if self.eat(">&") {
+            RedirectOp::DupWrite
+        } else if self.eat("<&") {
+            RedirectOp::DupRead
+        } else if self.eat(">") {
+            RedirectOp::Write
+        } else {
+            self.pos += 1;
+            RedirectOp::Read
+        }
+    }
+
+    fn word(&mut self) -> Result<String, LexError> {
+        let mut word = String::new();
+        while let Some(chr) = self.peek() {
+            match chr {
+                '<' | '>' if self.lookahead_substitution() => {
+                    let start = if chr == '<' { "<(" } else { ">(" };
+                    word.push_str(start);
+                    self.pos += 2;
+                    self.nested(&mut word, ')', start)?;
+                }
+                chr if is_meta(chr) => break,
+                '\\' if self.lookahead("\\\n") => self.pos += 2,
+                '\\' => {
+                    word.push(chr);
+                    self.pos += 1;
+                    if let Some(chr) = self.bump() {
+                        word.push(chr);
+                    }
+                }
+                '\'' => self.single_quoted(&mut word)?,
+                '"' => self.double_quoted(&mut word)?,
+                '`' => self.backquoted(&mut word)?,
+                '$' => self.dollar(&mut word)?,
+                _ => {
+                    word.push(chr);
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok(word)
+    }
+
+    fn single_quoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('\'');
+        self.pos += 1;
+        loop {
+            match self.bump() {
+                Some('\'') => break,
+                Some(chr) => word.push(chr),
+                None => return Err(LexError::UnterminatedQuote('\'')),
+            }
+        }
+        word.push('\'');
+        Ok(())
+    }
+
+    fn double_quoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('"');
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some('"') => break,
+                Some('\\') if self.lookahead("\\\n") => self.pos += 2,
+                Some('\\') => {
+                    word.push('\\');
+                    self.pos += 1;
+                    if let Some(chr) = self.bump() {
+                        word.push(chr);
+                    }
+                }
+                Some('`') => self.backquoted(word)?,
+                Some('$') => self.dollar(word)?,
+                Some(chr) => {
+                    word.push(chr);
+                    self.pos += 1;
+                }
+                None => return Err(LexError::UnterminatedQuote('"')),
+            }
+        }
+        self.pos += 1;
+        word.push('"');
+        Ok(())
+    }
+
+    fn backquoted(&mut self, word: &mut String) -> Result<(), LexError> {
+        word.push('`');
+        self.pos += 1;
+        loop {
+            match self.bump() {
+                Some('`') => break,
+                Some('\\') => {
+                    word.push('\\');
+                    if let Some(chr) = self.bump() {
+                        word.push(chr);
+                    }
+                }
+                Some(chr) => word.push(chr),
+                None => return Err(LexError::UnterminatedQuote('`')),
+            }
+        }
+        word.push('`');
+        Ok(())
+    }
+
+    /// Consume an expansion starting with `$`, which may contain characters that would
+    /// otherwise end the word
+    fn dollar(&mut self, word: &mut String) -> Result<(), LexError> {
+        if self.eat("${") {
+            word.push_str("${");
+            self.nested(word, '}', "${")
+        } else if self.eat("$(") {
+            word.push_str("$(");
+            self.nested(word, ')', "$(")
+        } else {
+            word.push('$');
+            self.pos += 1;
+            Ok(())
+        }
+    }
+
+    /// Consume the rest of an expansion up to and including its closing character, skipping
+    /// over anything quoted or nested inside it
+    fn nested(
+        &mut self,
+        word: &mut String,
+        close: char,
+        start: &'static str,
+    ) -> Result<(), LexError> {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(chr) if chr == close && depth == 0 => break,
+                Some('\\') => {
+                    word.push('\\');
+                    self.pos += 1;
+                    if let Some(chr) = self.bump() {
+                        word.push(chr);
+                    }
+                }
+                Some('\'') => self.single_quoted(word)?,
+                Some('"') => self.double_quoted(word)?,
+                Some('`') => self.backquoted(word)?,
+                Some('$') => self.dollar(word)?,
+                Some(chr) => {
+                    if close == ')' && chr == '(' {
+                        depth += 1;
+                    } else if close == ')' && chr == ')' {
+                        depth -= 1;
+                    }
+                    word.push(chr);
+                    self.pos += 1;
+                }
+                None => return Err(LexError::UnterminatedExpansion(start)),
+            }
+        }
+        self.pos += 1;
+        word.push(close);
+        Ok(())
+    }
+}
+
+/// Remove the quotes and backslashes from a here-document's delimiter
+fn unquote(word: &str) -> String {
+    let mut text = String::new();
+    let mut chars = word.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => text.extend(chars.next()),
+            '\'' | '"' => {}
+            chr => text.push(chr),
+        }
+    }
+    text
+}
+
+/// Find the end of a `$(...)` whose contents start at `start`, returning the index just past
+/// its closing parenthesis
+pub fn substitution_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut lexer = Lexer {
+        chars: chars.to_vec(),
+        pos: start,
+        here_docs: Vec::new(),
+    };
+    lexer.nested(&mut String::new(), ')', "$(").ok()?;
+    Some(lexer.pos)
+}
+
+/// Find the end of the quote or expansion starting at `start`, returning the index just past it
+pub fn quoted_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut lexer = Lexer {
+        chars: chars.to_vec(),
+        pos: start,
+        here_docs: Vec::new(),
+    };
+    let word = &mut String::new();
+    match chars.get(start)? {
+        '\'' => lexer.single_quoted(word),
+        '"' => lexer.double_quoted(word),
+        '`' => lexer.backquoted(word),
+        '$' => lexer.dollar(word),
+        '<' | '>' if chars.get(start + 1) == Some(&'(') => {
+            lexer.pos += 2;
+            lexer.nested(word, ')', "<(")
+        }
+        _ => return None,
+    }
+    .ok()?;
+    Some(lexer.pos)
+}
+
+/// Shorthand for running a [`Lexer`] over the input
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    Lexer::new(input).tokenize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<Token> {
+        words
+            .iter()
+            .map(|word| Token::Word(word.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_words_on_whitespace() {
+        assert_eq!(
+            tokenize("  echo  a\tb ").unwrap(),
+            words(&["echo", "a", "b"])
+        );
+    }
+
+    #[test]
+    fn keeps_quotes_and_escapes_in_words() {
+        assert_eq!(
+            tokenize(r#"echo 'a b' "c \" d" e\ f"#).unwrap(),
+            words(&["echo", "'a b'", r#""c \" d""#, r"e\ f"])
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_quotes() {
+        assert_eq!(tokenize("echo 'a"), Err(LexError::UnterminatedQuote('\'')));
+        assert_eq!(tokenize("echo \"a"), Err(LexError::UnterminatedQuote('"')));
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(
+            tokenize("echo a#b '#' http://x # the rest").unwrap(),
+            words(&["echo", "a#b", "'#'", "http://x"])
+        );
+        assert_eq!(
+            tokenize("echo a // the rest").unwrap(),
+            words(&["echo", "a"])
+        );
+        assert!(tokenize("// a whole line").unwrap().is_empty());
+        assert_eq!(
+            tokenize("echo a # one\necho b").unwrap(),
+            [
+                words(&["echo", "a"]),
+                vec![Token::Newline],
+                words(&["echo", "b"])
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! The eish shell as a library: [`parse`] turns input into an [`Ast`] and an [`Executor`] runs
+//! it, keeping the variables, functions and jobs around between runs
+
+mod arith;
+mod brace;
+mod builtins;
+mod exec;
+mod expand;
+mod glob;
+mod jobs;
+pub mod lexer;
+pub mod parser;
+mod vars;
+
+use exec::Flow;
+pub use exec::{signal_name, Io, Output};
+use jobs::{Job, JobControl};
+use parser::List;
+pub use parser::{is_incomplete, parse, Ast, ParseError};
+use std::{collections::HashMap, env, fs::File, path::PathBuf, rc::Rc};
+use vars::{environment, Variable};
+
+/// Runs commands, keeping what they share like variables, functions and jobs.
+///
+/// Pipelines, subshells and substitutions fork and keep running the executor in the child, which
+/// is only sound in a single-threaded process, so an executor must not be used while other
+/// threads are running.
+#[derive(Debug)]
+pub struct Executor {
+    pub path: PathBuf,
+    /// The status of the last command that ran, available as `$?`
+    pub status: i32,
+    /// Set by the `exit` builtin once the shell should stop
+    pub exit: bool,
+    /// Set by `break` and `continue` to leave loops
+    pub(crate) flow: Flow,
+    /// How many loops the commands being run are inside of
+    pub(crate) loops: usize,
+    pub(crate) jobs: Vec<Job>,
+    /// Only set if the shell is in charge of a terminal
+    pub(crate) job_control: Option<JobControl>,
+    /// The pid of the last background job, available as `$!`
+    pub(crate) background_pid: Option<libc::pid_t>,
+    pub(crate) vars: HashMap<String, Variable>,
+    /// `$0` followed by the positional parameters `$1` to `$n`
+    pub args: Vec<String>,
+    pub functions: HashMap<String, Rc<List>>,
+    /// For each function being run, the variables it made local along with their old values
+    pub(crate) locals: Vec<Vec<(String, Option<Variable>)>>,
+    pub aliases: HashMap<String, String>,
+    /// The aliases being run, which are not expanded again inside themselves
+    pub(crate) expanding: Vec<String>,
+    /// Abbreviations which are expanded while the first word of a command is typed
+    pub abbrs: HashMap<String, String>,
+    /// The status of the last command substitution in the command being expanded, which is the
+    /// status of a command with nothing but assignments
+    pub(crate) substitution_status: Option<i32>,
+    /// The streams of the list being run, which command and process substitutions inherit
+    pub(crate) streams: Option<Io>,
+    /// Our ends of the pipes of process substitutions, handed to commands as `/dev/fd/N`
+    pub(crate) substitutions: Vec<File>,
+    /// Processes started by process substitutions which haven't been reaped yet
+    pub(crate) substitution_pids: Vec<libc::pid_t>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    /// An executor with the process' environment variables, in its current directory
+    pub fn new() -> Self {
+        let mut sh = Executor {
+            path: PathBuf::new(),
+            status: 0,
+            exit: false,
+            flow: Flow::Normal,
+            loops: 0,
+            jobs: Vec::new(),
+            job_control: None,
+            background_pid: None,
+            vars: environment(),
+            args: vec![String::from("eish")],
+            functions: HashMap::new(),
+            locals: Vec::new(),
+            aliases: HashMap::new(),
+            expanding: Vec::new(),
+            abbrs: HashMap::new(),
+            substitution_status: None,
+            streams: None,
+            substitutions: Vec::new(),
+            substitution_pids: Vec::new(),
+        };
+        sh.path = env::current_dir()
+            .ok()
+            .or_else(|| sh.home().map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("/"));
+        sh.set_var("PWD", sh.path.display().to_string());
+        sh
+    }
+}
+
+/// Run input in a new executor and collect what it writes. Tests take turns, since executors
+/// fork and shouldn't do so while another test is running one.
+#[cfg(test)]
+pub(crate) fn run_test(input: &str) -> Output {
+    static TURN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _turn = TURN.lock().unwrap_or_else(|err| err.into_inner());
+    let mut sh = Executor::new();
+    sh.output(&parse(input).unwrap()).unwrap()
+}
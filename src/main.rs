@@ -1,19 +1,19 @@
 use crossterm::{
-    cursor::{MoveLeft, MoveTo},
+    cursor::{MoveTo, MoveToColumn, MoveUp},
     event::{self, Event, KeyCode, KeyModifiers},
     style::Stylize,
     terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
     QueueableCommand,
 };
+use eish::{is_incomplete, parse, signal_name, Executor, Io};
 use signal_hook::consts::SIGINT;
 use std::{
     env,
     error::Error,
     fmt::Display,
-    io::{stdout, ErrorKind, Stdout, Write},
+    fs,
+    io::{self, stdout, ErrorKind, IsTerminal, Read, Stdout, Write},
     path::PathBuf,
-    process::Command,
-    str::FromStr,
 };
 
 #[derive(Debug)]
@@ -22,79 +22,43 @@ pub enum Input {
     Exit,
 }
 
+/// The interactive shell, which reads commands from the terminal for its executor to run
 #[derive(Debug)]
 pub struct Shell {
+    pub executor: Executor,
     pub stdout: Stdout,
-    pub path: PathBuf,
     pub history: Vec<String>,
 }
 
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Shell {
+    pub fn new() -> Self {
+        Shell {
+            executor: Executor::new(),
+            stdout: stdout(),
+            history: Vec::new(),
+        }
+    }
+
     /// Handle input and return whether to exit or not
     pub fn handle_input(&mut self, input: Input) -> Result<bool, Box<dyn Error>> {
         match input {
             Input::Command(input) => {
-                if input.is_empty() {
-                    return Ok(false);
-                }
-                if input.starts_with("//") {
-                    return Ok(false);
-                }
-                let input: Vec<&str> = input.trim().split(' ').collect();
-                match input[0] {
-                    "exit" => {
-                        self.write("See you later, Bye!\r")?;
-                    }
-                    "cd" => {
-                        if input.len() == 1 {
-                            self.write(&self.path.to_str().unwrap().to_string())?;
-                        } else if input.len() == 2 {
-                            match PathBuf::from_str(
-                                &input[1].replace('~', &env::var("HOME").unwrap()),
-                            ) {
-                                Ok(path) => {
-                                    env::set_current_dir(path.clone()).unwrap();
-                                    self.path = env::current_dir().unwrap_or_else(|_| {
-                                        env::var("HOME").unwrap().parse().unwrap()
-                                    });
-                                }
-                                Err(err) => {
-                                    self.write(
-                                        format!("Error running command: {:#?}", err)
-                                            .replace('\n', "\r\n")
-                                            .red(),
-                                    )?;
-                                }
-                            }
-                        }
+                let list = match parse(&input) {
+                    Ok(list) => list,
+                    Err(err) => {
+                        self.write(format!("Error parsing command: {}", err).red())?;
+                        self.executor.status = 2;
+                        return Ok(false);
                     }
-                    _ => {
-                        let mut cmd = Command::new(input[0]);
-                        if input.len() > 1 {
-                            cmd.args(input[1..].iter());
-                        }
-                        match cmd.spawn() {
-                            Ok(mut cmd) => {
-                                disable_raw_mode()?;
-                                cmd.wait()?;
-                                enable_raw_mode()?;
-                            }
-                            Err(err) => match err.kind() {
-                                ErrorKind::NotFound => {
-                                    self.write("Unknown command")?;
-                                }
-                                _ => {
-                                    self.write(
-                                        format!("Error running command: {:#?}", err)
-                                            .replace('\n', "\r\n")
-                                            .red(),
-                                    )?;
-                                }
-                            },
-                        }
-                    }
-                }
-                Ok(false)
+                };
+                self.executor.run(&list)?;
+                Ok(self.executor.exit)
             }
             Input::Exit => Ok(true),
         }
@@ -102,50 +66,43 @@ impl Shell {
 
     pub fn get_input(&mut self) -> Result<Input, Box<dyn Error>> {
         let mut input = String::new();
+        // where the cursor is, as a byte offset into the input
         let mut idx = 0;
+        // the line of the input the cursor is on
+        let mut row = 0;
         let input_idx = self.history.len();
         let mut history_idx = input_idx;
         self.history.push(String::new());
+        self.executor.notify_jobs()?;
+        let raw_mode = RawMode::enable()?;
         write!(
             self.stdout,
-            "\r\x1b[2K{}-{} {}\r\n\x1b[2K{} {}",
+            "\r\x1b[2K{}-{} {}{}\r\n\x1b[2K{} {}",
             idx.to_string().blue(),
             input.len().to_string().red(),
-            self.path.to_str().unwrap().green(),
+            self.executor.path.to_str().unwrap().green(),
+            self.status_indicator(),
             "~>".magenta(),
             input
         )?;
         self.stdout.flush()?;
         loop {
-            write!(
-                self.stdout,
-                "\x1b[F\x1b[2K{}-{} {}\r\n\x1b[2K{} {}",
-                idx.to_string().blue(),
-                input.len().to_string().red(),
-                self.path.to_str().unwrap().green(),
-                "~>".magenta(),
-                input
-            )?;
-            if !input.is_empty() && input.len() > idx {
-                self.stdout.queue(MoveLeft((input.len() - idx) as u16))?;
-            }
-            self.stdout.flush()?;
+            row = self.render_input(&input, idx, row)?;
             if let Event::Key(key) = event::read()? {
                 match key.code {
                     KeyCode::Char(chr) => {
                         if key.modifiers.contains(KeyModifiers::CONTROL) {
                             match chr {
-                                'd' if input.is_empty() => {
-                                    return Ok(Input::Exit);
-                                }
+                                'd' if input.is_empty() => return Ok(Input::Exit),
                                 'c' => {
                                     idx = 0;
                                     write!(
                                         self.stdout,
-                                        "\r\n\x1b[2F\x1b[2K{} {}\r\n\x1b[2K\n",
-                                        "~>".magenta(),
-                                        input
+                                        "\r\x1b[{}A\x1b[J{}\r\n\r\n",
+                                        row + 1,
+                                        prompted(&input)
                                     )?;
+                                    row = 0;
                                     input.drain(..);
                                 }
                                 'l' => {
@@ -156,59 +113,57 @@ impl Shell {
                                 _ => {}
                             }
                         } else {
+                            if chr == ' ' {
+                                self.expand_abbr(&mut input, &mut idx);
+                            }
                             input.insert(idx, chr);
-                            idx += 1;
+                            idx += chr.len_utf8();
                         }
                     }
                     KeyCode::Enter => {
-                        // newline
-                        self.write("")?;
-                        break;
-                    }
-                    KeyCode::Backspace => {
-                        if !input.is_empty() {
-                            input.remove(idx - 1);
-                            idx -= 1;
+                        self.expand_abbr(&mut input, &mut idx);
+                        if !is_incomplete(&input) {
+                            break;
                         }
+                        input.insert(idx, '\n');
+                        idx += 1;
                     }
-                    KeyCode::Left => {
-                        if idx != 0 {
-                            idx -= 1;
-                        }
+                    KeyCode::Backspace if idx != 0 => {
+                        idx = prev_char(&input, idx);
+                        input.remove(idx);
                     }
-                    KeyCode::Right => {
-                        if idx < input.len() {
-                            idx += 1;
-                        }
+                    KeyCode::Left if idx != 0 => {
+                        idx = prev_char(&input, idx);
                     }
-                    KeyCode::Up => {
-                        if history_idx != 0 {
-                            if history_idx == input_idx {
-                                self.history[input_idx] = input.clone();
-                            }
-                            history_idx -= 1;
-                            input = self.history[history_idx].clone();
-                            idx = input.len();
-                        }
+                    KeyCode::Right if idx < input.len() => {
+                        idx += input[idx..].chars().next().map_or(0, char::len_utf8);
                     }
-                    KeyCode::Down => {
-                        if history_idx < self.history.len() - 1 {
-                            history_idx += 1;
-                            input = self.history[history_idx].clone();
-                            idx = input.len();
+                    KeyCode::Up if history_idx != 0 => {
+                        if history_idx == input_idx {
+                            self.history[input_idx] = input.clone();
                         }
+                        history_idx -= 1;
+                        input = self.history[history_idx].clone();
+                        idx = input.len();
+                    }
+                    KeyCode::Down if history_idx < self.history.len() - 1 => {
+                        history_idx += 1;
+                        input = self.history[history_idx].clone();
+                        idx = input.len();
                     }
                     _ => {}
                 }
             }
         }
+        // replace the status line with the finished command
         write!(
             self.stdout,
-            "\x1b[2F\x1b[2K{} {}\r\n\x1b[2K",
-            "~>".magenta(),
-            input
+            "\r\x1b[{}A\x1b[J{}\r\n",
+            row + 1,
+            prompted(&input)
         )?;
         self.stdout.flush()?;
+        drop(raw_mode);
         if history_idx == 0 {
             self.history[input_idx] = input.clone();
         } else if let Some(entry) = self.history.get(history_idx - 1) {
@@ -223,6 +178,61 @@ impl Shell {
         Ok(Input::Command(input))
     }
 
+    /// Redraw the status line and the input below it, starting from the line of the input the
+    /// cursor is on, and return the line the cursor ends up on. `idx` is a byte offset, while
+    /// everything shown counts characters.
+    fn render_input(&mut self, input: &str, idx: usize, row: usize) -> io::Result<usize> {
+        let before = &input[..idx];
+        write!(
+            self.stdout,
+            "\r\x1b[{}A\x1b[J{}-{} {}{}\r\n{}",
+            row + 1,
+            before.chars().count().to_string().blue(),
+            input.chars().count().to_string().red(),
+            self.executor.path.to_str().unwrap().green(),
+            self.status_indicator(),
+            prompted(input)
+        )?;
+        let row = before.matches('\n').count();
+        let lines = input.matches('\n').count();
+        if lines > row {
+            self.stdout.queue(MoveUp((lines - row) as u16))?;
+        }
+        let line = before.rsplit('\n').next().unwrap_or_default();
+        // both prompts are three columns wide
+        self.stdout
+            .queue(MoveToColumn((line.chars().count() + 3) as u16))?;
+        self.stdout.flush()?;
+        Ok(row)
+    }
+
+    /// Expand the first word of the input if it is an abbreviation and the cursor is at its end
+    fn expand_abbr(&self, input: &mut String, idx: &mut usize) {
+        let start = input.len() - input.trim_start().len();
+        if *idx <= start || !input[*idx..].chars().next().is_none_or(char::is_whitespace) {
+            return;
+        }
+        let word = &input[start..*idx];
+        if word.contains(char::is_whitespace) {
+            return;
+        }
+        if let Some(expansion) = self.executor.abbrs.get(word) {
+            input.replace_range(start..*idx, expansion);
+            *idx = start + expansion.len();
+        }
+    }
+
+    /// The last command's status for the prompt, empty if it succeeded
+    fn status_indicator(&self) -> String {
+        match self.executor.status {
+            0 => String::new(),
+            status => match signal_name(status.wrapping_sub(128)) {
+                Some(name) => format!(" [{}]", name).red().to_string(),
+                None => format!(" [{}]", status).red().to_string(),
+            },
+        }
+    }
+
     pub fn write(&mut self, input: impl Display) -> Result<(), Box<dyn Error>> {
         writeln!(self.stdout, "{}\r", input)?;
         self.stdout.flush()?;
@@ -230,7 +240,132 @@ impl Shell {
     }
 }
 
+/// Keeps the terminal in raw mode while it is alive, so that it is restored however reading input
+/// ends, errors included
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Where the character before the byte offset `idx` starts
+fn prev_char(input: &str, idx: usize) -> usize {
+    input[..idx]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+/// The lines of the input, the first after the prompt and the rest after the continuation prompt
+fn prompted(input: &str) -> String {
+    input
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let prompt = if i == 0 { "~>" } else { " >" };
+            format!("{} {}", prompt.magenta(), line)
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// The first rc file that exists out of `$XDG_CONFIG_HOME/eish/eishrc`,
+/// `~/.config/eish/eishrc` and `~/.eishrc`
+fn rc_file(sh: &Executor) -> Option<PathBuf> {
+    let home = sh.home().map(PathBuf::from);
+    let config = sh
+        .get_var("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(home.as_ref()?.join(".config")));
+    [
+        config.map(|config| config.join("eish").join("eishrc")),
+        home.map(|home| home.join(".eishrc")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.is_file())
+}
+
+/// A script to run instead of reading commands from the terminal
+struct Script {
+    /// What `$0` is set to
+    name: String,
+    source: String,
+    args: Vec<String>,
+}
+
+/// Work out what to run from the command line arguments, exiting on bad ones
+fn script(args: &[String]) -> Option<Script> {
+    let fail = |msg: String, status| -> ! {
+        eprintln!("eish: {}", msg);
+        std::process::exit(status)
+    };
+    match args.get(1).map(String::as_str) {
+        Some("-c") => {
+            let source = args
+                .get(2)
+                .unwrap_or_else(|| fail(String::from("-c: option requires an argument"), 2));
+            Some(Script {
+                name: args.get(3).cloned().unwrap_or_else(|| String::from("eish")),
+                source: source.clone(),
+                args: args.iter().skip(4).cloned().collect(),
+            })
+        }
+        Some(option) if option.starts_with('-') => fail(format!("{}: invalid option", option), 2),
+        Some(path) => {
+            let source = fs::read_to_string(path).unwrap_or_else(|err| {
+                let status = if err.kind() == ErrorKind::NotFound {
+                    127
+                } else {
+                    126
+                };
+                fail(format!("{}: {}", path, err), status)
+            });
+            Some(Script {
+                name: path.to_string(),
+                source,
+                args: args[2..].to_vec(),
+            })
+        }
+        None if !io::stdin().is_terminal() => {
+            let mut source = String::new();
+            if let Err(err) = io::stdin().read_to_string(&mut source) {
+                fail(format!("stdin: {}", err), 1);
+            }
+            Some(Script {
+                name: String::from("eish"),
+                source,
+                args: Vec::new(),
+            })
+        }
+        None => None,
+    }
+}
+
 fn main() {
+    let mut sh = Shell::new();
+    if let Some(script) = script(&env::args().collect::<Vec<String>>()) {
+        let executor = &mut sh.executor;
+        executor.args = vec![script.name.clone()];
+        executor.args.extend(script.args);
+        let status = executor
+            .run_script(&script.name, &script.source)
+            .unwrap_or_else(|err| {
+                eprintln!("{}: {}", script.name, err);
+                1
+            });
+        std::process::exit(status);
+    }
     let hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |p| {
         disable_raw_mode().unwrap();
@@ -239,17 +374,33 @@ fn main() {
     unsafe {
         signal_hook::low_level::register(SIGINT, || {}).unwrap();
     }
-    enable_raw_mode().unwrap();
-    let mut sh = Shell {
-        stdout: stdout(),
-        path: env::current_dir().unwrap_or_else(|_| env::var("HOME").unwrap().parse().unwrap()),
-        history: Vec::new(),
-    };
-    sh.write("Welcome to EISH").unwrap();
-    while let Ok(input) = sh.get_input() {
-        if sh.handle_input(input).unwrap() {
+    if let Err(err) = sh.executor.init_job_control() {
+        sh.write(format!("Could not take control of the terminal: {}", err).red())
+            .unwrap();
+    }
+    if let Some(path) = rc_file(&sh.executor) {
+        let io = Io::inherit().unwrap();
+        if let Err(err) = sh.executor.source_file(&path, &[], &io) {
+            sh.write(format!("Could not load {}: {}", path.display(), err).red())
+                .unwrap();
+        }
+    }
+    if !sh.executor.exit {
+        sh.write("Welcome to EISH").unwrap();
+    }
+    while !sh.executor.exit {
+        let Ok(input) = sh.get_input() else {
             break;
+        };
+        match sh.handle_input(input) {
+            Ok(true) => break,
+            Ok(false) => {}
+            // the command couldn't be run, but the shell can still take the next one
+            Err(err) => {
+                sh.executor.status = 1;
+                let _ = sh.write(format!("{}", err).red());
+            }
         }
     }
-    disable_raw_mode().unwrap();
+    std::process::exit(sh.executor.status);
 }
@@ -0,0 +1,755 @@
+use crate::{
+    lexer::{tokenize, HereDoc, LexError, RedirectOp, Token},
+    vars::{is_name, split_assignment},
+};
+use std::{error::Error, fmt::Display, rc::Rc};
+
+/// A redirection of one of a command's file descriptors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub op: RedirectOp,
+    /// The raw file name or file descriptor being redirected to, or the delimiter of a
+    /// here-document
+    pub target: String,
+    pub here_doc: Option<HereDoc>,
+}
+
+/// A single command and its arguments, still in their raw form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// `name=value` assignments before the command name
+    pub assignments: Vec<(String, String)>,
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// `if` with any number of `elif`s and an optional `else`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct If {
+    /// Each condition with the list it runs if it succeeds
+    pub branches: Vec<(List, List)>,
+    pub otherwise: Option<List>,
+}
+
+/// A `while` loop, or an `until` loop which runs as long as its condition fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct While {
+    pub until: bool,
+    pub condition: List,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct For {
+    pub name: String,
+    /// The raw words after `in`, or `None` to loop over the positional parameters
+    pub words: Option<Vec<String>>,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub word: String,
+    /// The raw patterns of each item with the list it runs
+    pub items: Vec<(Vec<String>, List)>,
+}
+
+/// A command built out of other commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compound {
+    /// A `((...))` arithmetic command with its raw expression
+    Arith(String),
+    If(If),
+    While(While),
+    For(For),
+    Case(Case),
+    /// `( ... )`, run in a copy of the shell
+    Subshell(List),
+    /// `{ ...; }`, run in the shell itself
+    Group(List),
+}
+
+/// A function definition, `name() { ... }` or `fn name { ... }`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub body: Rc<List>,
+}
+
+/// Anything which can be a stage of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// A compound command with the redirections that apply to all of it
+    Compound(Compound, Vec<Redirect>),
+    Function(Function),
+}
+
+/// Commands connected with `|`, each one's stdout feeding the next one's stdin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`, run the next pipeline only if the previous one succeeded
+    And,
+    /// `||`, run the next pipeline only if the previous one failed
+    Or,
+}
+
+/// Pipelines joined with `&&` and `||`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Whether this was followed by `&` to run it as a background job
+    pub background: bool,
+}
+
+/// A sequence of commands separated by `;` or newlines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let default = match self.op {
+            RedirectOp::Read
+            | RedirectOp::DupRead
+            | RedirectOp::HereDoc
+            | RedirectOp::HereDocStrip
+            | RedirectOp::HereString => 0,
+            _ => 1,
+        };
+        if self.fd != default {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{}{}", self.op, self.target)
+    }
+}
+
+impl Display for SimpleCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let assignments = self
+            .assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value));
+        let words = self.words.iter().map(ToString::to_string);
+        let redirects = self.redirects.iter().map(ToString::to_string);
+        let parts: Vec<String> = assignments.chain(words).chain(redirects).collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// A list followed by whatever separates it from a keyword after it
+fn terminated(list: &List) -> String {
+    match list.items.last() {
+        Some(and_or) if and_or.background => format!("{} ", list),
+        _ => format!("{}; ", list),
+    }
+}
+
+impl Display for Compound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compound::Arith(expr) => write!(f, "(({}))", expr),
+            Compound::If(clause) => {
+                for (i, (condition, body)) in clause.branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    write!(
+                        f,
+                        "{} {}then {}",
+                        keyword,
+                        terminated(condition),
+                        terminated(body)
+                    )?;
+                }
+                if let Some(otherwise) = &clause.otherwise {
+                    write!(f, "else {}", terminated(otherwise))?;
+                }
+                write!(f, "fi")
+            }
+            Compound::While(clause) => write!(
+                f,
+                "{} {}do {}done",
+                if clause.until { "until" } else { "while" },
+                terminated(&clause.condition),
+                terminated(&clause.body)
+            ),
+            Compound::For(clause) => {
+                write!(f, "for {}", clause.name)?;
+                if let Some(words) = &clause.words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {}", word)?;
+                    }
+                }
+                write!(f, "; do {}done", terminated(&clause.body))
+            }
+            Compound::Case(clause) => {
+                write!(f, "case {} in ", clause.word)?;
+                for (patterns, body) in &clause.items {
+                    write!(f, "{}) {};; ", patterns.join(" | "), body)?;
+                }
+                write!(f, "esac")
+            }
+            Compound::Subshell(list) => write!(f, "({})", list),
+            Compound::Group(list) => write!(f, "{{ {}}}", terminated(list)),
+        }
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Simple(command) => write!(f, "{}", command),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{}", compound)?;
+                for redirect in redirects {
+                    write!(f, " {}", redirect)?;
+                }
+                Ok(())
+            }
+            Command::Function(function) => {
+                write!(f, "{}() {{ {}}}", function.name, terminated(&function.body))
+            }
+        }
+    }
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let commands: Vec<String> = self.commands.iter().map(ToString::to_string).collect();
+        write!(f, "{}", commands.join(" | "))
+    }
+}
+
+impl Display for AndOr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            match connector {
+                Connector::And => write!(f, " && {}", pipeline)?,
+                Connector::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", and_or)?;
+            if and_or.background {
+                write!(f, " &")?;
+            } else if i != self.items.len() - 1 {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Words which end the list before them when they come where a command could start
+const TERMINATORS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Whether a word can be the name of a function
+fn is_function_name(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|chr| chr.is_alphanumeric() || matches!(chr, '_' | '-' | '.' | ':'))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Lex(LexError),
+    Unexpected(Token),
+    UnexpectedEof,
+    /// A redirection of a file descriptor other than 0, 1 or 2, which commands aren't given
+    BadFd(i32),
+    /// A `<&-` or `>&-` redirection, since streams can be replaced but not closed
+    Close(RedirectOp),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Lex(err) => write!(f, "{}", err),
+            ParseError::Unexpected(token) => write!(f, "unexpected token `{}`", token),
+            ParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+            ParseError::BadFd(fd) => write!(
+                f,
+                "can't redirect file descriptor {}, only 0, 1 and 2 are supported",
+                fd
+            ),
+            ParseError::Close(op) => {
+                write!(f, "closing file descriptors with {}- isn't supported", op)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> Self {
+        ParseError::Lex(err)
+    }
+}
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Parse the whole token stream
+    pub fn parse(mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        match self.peek() {
+            Some(_) => Err(self.unexpected()),
+            None => Ok(list),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::Newline) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the next token is the given unquoted keyword
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if !self.keyword(keyword) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.peek() != Some(&token) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        self.skip_newlines();
+        while let Some(Token::Word(_) | Token::Redirect(..) | Token::Arith(_) | Token::LParen) =
+            self.peek()
+        {
+            if TERMINATORS.iter().any(|keyword| self.keyword(keyword)) {
+                break;
+            }
+            let mut and_or = self.and_or()?;
+            match self.peek() {
+                Some(Token::Amp) => {
+                    and_or.background = true;
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                Some(Token::Semi | Token::Newline) => {
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                _ => {
+                    items.push(and_or);
+                    break;
+                }
+            }
+            items.push(and_or);
+        }
+        Ok(List { items })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.command()?];
+        while let Some(Token::Pipe) = self.peek() {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    /// A list which has to have at least one command in it, like the body of a loop
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        if list.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(list)
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let compound = match self.peek() {
+            Some(Token::Arith(expr)) => {
+                let expr = expr.clone();
+                self.pos += 1;
+                Compound::Arith(expr)
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let list = self.compound_list()?;
+                self.expect(Token::RParen)?;
+                Compound::Subshell(list)
+            }
+            Some(Token::Word(word)) => match word.as_str() {
+                "{" => {
+                    self.pos += 1;
+                    let list = self.compound_list()?;
+                    self.expect_keyword("}")?;
+                    Compound::Group(list)
+                }
+                "if" => self.if_clause()?,
+                "while" | "until" => self.while_clause()?,
+                "for" => self.for_clause()?,
+                "case" => self.case_clause()?,
+                "fn" => {
+                    self.pos += 1;
+                    let name = match self.peek() {
+                        Some(Token::Word(name)) if is_function_name(name) => name.clone(),
+                        _ => return Err(self.unexpected()),
+                    };
+                    self.pos += 1;
+                    return self.function(name);
+                }
+                word if is_function_name(word)
+                    && self.tokens.get(self.pos + 1) == Some(&Token::LParen) =>
+                {
+                    let name = word.to_string();
+                    self.pos += 2;
+                    self.expect(Token::RParen)?;
+                    return self.function(name);
+                }
+                _ => return Ok(Command::Simple(self.simple_command()?)),
+            },
+            _ => return Ok(Command::Simple(self.simple_command()?)),
+        };
+        let mut redirects = Vec::new();
+        while let Some(Token::Redirect(fd, op)) = self.peek() {
+            let (fd, op) = (*fd, *op);
+            self.pos += 1;
+            redirects.push(self.redirect(fd, op)?);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    /// The `{ ... }` body of a function definition
+    fn function(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        self.expect_keyword("{")?;
+        let body = self.compound_list()?;
+        self.expect_keyword("}")?;
+        Ok(Command::Function(Function {
+            name,
+            body: Rc::new(body),
+        }))
+    }
+
+    fn if_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_keyword("then")?;
+            branches.push((condition, self.compound_list()?));
+            if !self.keyword("elif") {
+                break;
+            }
+            self.pos += 1;
+        }
+        let otherwise = if self.keyword("else") {
+            self.pos += 1;
+            Some(self.compound_list()?)
+        } else {
+            None
+        };
+        self.expect_keyword("fi")?;
+        Ok(Compound::If(If {
+            branches,
+            otherwise,
+        }))
+    }
+
+    fn while_clause(&mut self) -> Result<Compound, ParseError> {
+        let until = self.keyword("until");
+        self.pos += 1;
+        let condition = self.compound_list()?;
+        self.expect_keyword("do")?;
+        let body = self.compound_list()?;
+        self.expect_keyword("done")?;
+        Ok(Compound::While(While {
+            until,
+            condition,
+            body,
+        }))
+    }
+
+    fn for_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let name = match self.peek() {
+            Some(Token::Word(word)) if is_name(word) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let words = if self.keyword("in") {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.pos += 1;
+            }
+            match self.peek() {
+                Some(Token::Semi | Token::Newline) => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+            Some(words)
+        } else {
+            if let Some(Token::Semi) = self.peek() {
+                self.pos += 1;
+            }
+            None
+        };
+        self.skip_newlines();
+        self.expect_keyword("do")?;
+        let body = self.compound_list()?;
+        self.expect_keyword("done")?;
+        Ok(Compound::For(For { name, words, body }))
+    }
+
+    fn case_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let word = self.word()?;
+        self.skip_newlines();
+        self.expect_keyword("in")?;
+        self.skip_newlines();
+        let mut items = Vec::new();
+        while !self.keyword("esac") {
+            if let Some(Token::LParen) = self.peek() {
+                self.pos += 1;
+            }
+            let mut patterns = vec![self.word()?];
+            while let Some(Token::Pipe) = self.peek() {
+                self.pos += 1;
+                patterns.push(self.word()?);
+            }
+            self.expect(Token::RParen)?;
+            let body = self.list()?;
+            items.push((patterns, body));
+            match self.peek() {
+                Some(Token::DoubleSemi) => {
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                // the last item doesn't need a `;;`
+                _ => break,
+            }
+        }
+        self.expect_keyword("esac")?;
+        Ok(Compound::Case(Case { word, items }))
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    match split_assignment(word) {
+                        Some((name, value)) if words.is_empty() => {
+                            assignments.push((name.to_string(), value.to_string()));
+                        }
+                        _ => words.push(word.clone()),
+                    }
+                    self.pos += 1;
+                }
+                Some(Token::Redirect(fd, op)) => {
+                    let (fd, op) = (*fd, *op);
+                    self.pos += 1;
+                    redirects.push(self.redirect(fd, op)?);
+                }
+                _ => break,
+            }
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(SimpleCommand {
+            assignments,
+            words,
+            redirects,
+        })
+    }
+
+    fn redirect(&mut self, fd: Option<i32>, op: RedirectOp) -> Result<Redirect, ParseError> {
+        let (target, here_doc) = match self.peek() {
+            Some(Token::HereDoc(delimiter, doc)) => (delimiter.clone(), Some(doc.clone())),
+            Some(Token::Word(word)) => (word.clone(), None),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        let fd = fd.unwrap_or(match op {
+            RedirectOp::Read
+            | RedirectOp::DupRead
+            | RedirectOp::HereDoc
+            | RedirectOp::HereDocStrip
+            | RedirectOp::HereString => 0,
+            _ => 1,
+        });
+        if !(0..=2).contains(&fd) {
+            return Err(ParseError::BadFd(fd));
+        }
+        if matches!(op, RedirectOp::DupRead | RedirectOp::DupWrite) {
+            match target.parse() {
+                Ok(fd) if !(0..=2).contains(&fd) => return Err(ParseError::BadFd(fd)),
+                _ if target == "-" => return Err(ParseError::Close(op)),
+                _ => {}
+            }
+        }
+        Ok(Redirect {
+            fd,
+            op,
+            target,
+            here_doc,
+        })
+    }
+
+    /// The error for whatever token is at the current position
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::Unexpected(token.clone()),
+            None => ParseError::UnexpectedEof,
+        }
+    }
+}
+
+/// The syntax tree of a whole input
+pub type Ast = List;
+
+/// Tokenize and parse a line of input
+pub fn parse(input: &str) -> Result<Ast, ParseError> {
+    Parser::new(tokenize(input)?).parse()
+}
+
+/// Whether the input so far is only the start of a command, which goes on in the next line
+pub fn is_incomplete(input: &str) -> bool {
+    // a backslash at the very end escapes the newline that would come after it
+    let continued = tokenize(&format!("{}\n", input))
+        .is_ok_and(|tokens| tokens.last().is_some_and(|token| *token != Token::Newline));
+    continued
+        || matches!(
+            parse(input),
+            Err(ParseError::UnexpectedEof
+                | ParseError::Lex(
+                    LexError::UnterminatedQuote(_)
+                        | LexError::UnterminatedExpansion(_)
+                        | LexError::MissingDelimiter(_)
+                ))
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_incomplete_input() {
+        assert!(is_incomplete("while true; do"));
+        assert!(is_incomplete("echo 'open"));
+        assert!(is_incomplete("echo a \\"));
+        assert!(is_incomplete("echo a |"));
+        assert!(!is_incomplete("echo done"));
+        assert!(!is_incomplete("echo )"));
+    }
+
+    #[test]
+    fn displays_what_was_parsed() {
+        let ast = parse("if true;then echo a  >out; fi&&echo b|cat &\nf() { echo c; }").unwrap();
+        assert_eq!(
+            ast.to_string(),
+            "if true; then echo a >out; fi && echo b | cat & f() { echo c; }"
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let error = |input| parse(input).unwrap_err().to_string();
+        assert_eq!(error("echo )"), "unexpected token `)`");
+        assert_eq!(error("if true"), "unexpected end of input");
+        assert_eq!(error("echo 'a"), "unterminated quote '");
+        assert_eq!(
+            error("echo a 3> f"),
+            "can't redirect file descriptor 3, only 0, 1 and 2 are supported"
+        );
+        assert_eq!(
+            error("echo a 2>&4"),
+            "can't redirect file descriptor 4, only 0, 1 and 2 are supported"
+        );
+        assert_eq!(
+            error("echo a >&-"),
+            "closing file descriptors with >&- isn't supported"
+        );
+        assert_eq!(
+            error("cat <&-"),
+            "closing file descriptors with <&- isn't supported"
+        );
+    }
+}
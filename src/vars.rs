@@ -0,0 +1,162 @@
+use crate::Executor;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{CStr, CString},
+    mem, process, ptr,
+};
+
+/// A shell variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub value: String,
+    /// Whether the variable is passed on to the environment of commands
+    pub exported: bool,
+}
+
+/// Whether a string is a valid variable name
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|chr| chr == '_' || chr.is_ascii_alphabetic())
+        && chars.all(|chr| chr == '_' || chr.is_ascii_alphanumeric())
+}
+
+/// Split a `name=value` assignment word, if it is one
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_name(name).then_some((name, value))
+}
+
+/// The variables the shell starts with, taken from its environment, leaving out any that
+/// aren't valid UTF-8
+pub fn environment() -> HashMap<String, Variable> {
+    env::vars_os()
+        .filter_map(|(name, value)| {
+            Some((
+                name.into_string().ok()?,
+                Variable {
+                    value: value.into_string().ok()?,
+                    exported: true,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Look up a user's home directory in the passwd database, or the current user's if no name
+/// is given
+pub fn user_home(name: Option<&str>) -> Option<String> {
+    let name = match name {
+        Some(name) => Some(CString::new(name).ok()?),
+        None => None,
+    };
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    let mut result = ptr::null_mut();
+    loop {
+        let err = unsafe {
+            match &name {
+                Some(name) => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+                None => libc::getpwuid_r(
+                    libc::getuid(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            }
+        };
+        if err != libc::ERANGE {
+            break;
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+    if result.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+impl Executor {
+    /// The current user's home directory, from `$HOME` or the passwd database if it is unset
+    pub fn home(&self) -> Option<String> {
+        self.get_var("HOME").or_else(|| user_home(None))
+    }
+
+    /// Look up the value of a variable or special parameter
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "!" => self.background_pid.map(|pid| pid.to_string()),
+            "$" => Some(process::id().to_string()),
+            "#" => Some((self.args.len() - 1).to_string()),
+            "@" | "*" => Some(self.args[1..].join(" ")),
+            _ if name.chars().all(|chr| chr.is_ascii_digit()) => {
+                self.args.get(name.parse::<usize>().ok()?).cloned()
+            }
+            _ => self.vars.get(name).map(|var| var.value.clone()),
+        }
+    }
+
+    /// The variables passed on to the environment of commands
+    pub fn exported_vars(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name, &var.value))
+    }
+
+    /// Run `f` with some variables exported, restoring their old values afterwards
+    pub fn with_vars<T>(
+        &mut self,
+        assignments: Vec<(String, String)>,
+        f: impl FnOnce(&mut Executor) -> T,
+    ) -> T {
+        let saved: Vec<(String, Option<Variable>)> = assignments
+            .iter()
+            .map(|(name, _)| (name.clone(), self.vars.get(name).cloned()))
+            .collect();
+        for (name, value) in assignments {
+            self.vars.insert(
+                name,
+                Variable {
+                    value,
+                    exported: true,
+                },
+            );
+        }
+        let result = f(self);
+        for (name, var) in saved.into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+        result
+    }
+
+    /// Set a variable, keeping it exported if it already was
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                self.vars.insert(
+                    name.to_string(),
+                    Variable {
+                        value,
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+}